@@ -0,0 +1,52 @@
+//! Compares serial engine against process_parallel on generated input, and serial run of csv against run_pipelined,
+//! which parses rows on other threads while engine applies them.
+//! Run with `cargo run --release --example parallel_bench [rows]`, rows defaults to a million.
+
+use std::{env, time::Instant};
+
+use payment_engine::{
+    parallel::process_parallel,
+    processor::{run_pipelined, run_with_options},
+    transaction::{options::ParseOptions, RawTransaction},
+    InMemoryTransactionEngine,
+    InMemoryTransactionEngineBuilder,
+    TransactionEngine};
+
+fn transactions(rows: u32) -> Vec<RawTransaction> {
+    (0..rows).map(|transaction_id| {
+        let client_id = (transaction_id % u16::MAX as u32) as u64;
+        match transaction_id % 4 {
+            3 => RawTransaction::new(&format!("withdrawal, {}, {}, 0.5", client_id, transaction_id)),
+            _ => RawTransaction::new(&format!("deposit, {}, {}, 1.25", client_id, transaction_id)),
+        }
+    }).collect()
+}
+
+fn main() {
+    let rows = env::args().nth(1).map(|rows| rows.parse().expect("rows should be a number")).unwrap_or(1_000_000);
+    let input = transactions(rows);
+    let csv: String = input.iter().map(|transaction| format!("{}\n", transaction)).collect();
+
+    let start = Instant::now();
+    let engine = InMemoryTransactionEngine::new();
+    for transaction in input.clone() {
+        let _ = engine.add_transaction(transaction);
+    }
+    let serial_clients = engine.snap_shot_clients().len();
+    println!("serial: {} rows, {} clients in {:?}", rows, serial_clients, start.elapsed());
+
+    let builder = InMemoryTransactionEngineBuilder::new();
+    let start = Instant::now();
+    let parallel_clients = process_parallel(builder.config(), input).len();
+    println!("parallel ({} workers): {} rows, {} clients in {:?}",
+        builder.config().workers, rows, parallel_clients, start.elapsed());
+
+    let start = Instant::now();
+    let report = run_with_options(csv.as_bytes(), &mut InMemoryTransactionEngine::new(), &ParseOptions::default());
+    println!("serial csv: {} rows accepted in {:?}", report.summary.accepted, start.elapsed());
+
+    let start = Instant::now();
+    let report = run_pipelined(csv.as_bytes(), &mut InMemoryTransactionEngine::new(), &ParseOptions::default(), builder.config().workers);
+    println!("pipelined csv ({} parsers): {} rows accepted in {:?}",
+        builder.config().workers, report.summary.accepted, start.elapsed());
+}
@@ -1,78 +1,566 @@
-use std::fmt::Display;
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::fmt::Display;
 
-use crate::transaction::Transaction;
+use log::{error, warn};
+use serde::{ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Debug, Clone)]
+use crate::{amount::Amount, error::TransactionError, transaction::{RawTransaction, Transaction}};
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Client {
-    id: u16,
-    available: f64,
-    held: f64,
+    id: u64,
+    available: Amount,
+    held: Amount,
     locked: bool,
+    /// how far below zero withdrawals can take available balance.
+    overdraft_limit: Amount,
+    /// part of disputed amounts which could not be held for lack of available funds, still to be held.
+    pending_hold: Amount,
+    /// fees taken out of held, less interest added to it, not yet settled against amounts leaving held.
+    held_fee: Amount,
 }
 
 impl Client {
-    pub fn new(id: u16) -> Client {
+    pub fn new(id: u64) -> Client {
         Client{
             id,
-            available: 0.0,
-            held: 0.0,
+            available: Amount::ZERO,
+            held: Amount::ZERO,
             locked: false,
+            overdraft_limit: Amount::ZERO,
+            pending_hold: Amount::ZERO,
+            held_fee: Amount::ZERO,
         }
     }
 
-    pub fn apply_transaction(&mut self, transaction: &Transaction, amount: f64) -> bool {
+    /// Dispute, Resolve and Chargeback applied here are taken to be about a deposit.
+    /// Use apply_dispute_transaction when disputed transaction can also be a withdrawal.
+    /// Deposit and Capture first fill pending hold. Resolve and Chargeback first settle pending hold,
+    /// taken to belong to dispute being settled, chargeback taking it out of available.
+    /// What leaves held, on Resolve, Chargeback or Capture, is less by fee taken out of held, see charge_fee.
+    ///
+    /// effective_amount is the only amount applied, whatever the kind of transaction. For Dispute, Resolve,
+    /// Chargeback and Capture it is amount of referred transaction, looked up by caller. Amount carried by
+    /// Deposit, Withdrawal and PendingDeposit is ignored, so caller passes that in as effective_amount.
+    /// Debug builds assert the two agree.
+    ///
+    /// Client is left as it was when transaction is refused, for locked account, withdrawal beyond available
+    /// and overdraft, or balance that would overflow.
+    pub fn apply_transaction(&mut self, transaction: &RawTransaction, effective_amount: Amount) -> Result<(), TransactionError> {
+        if let RawTransaction::Deposit { client_id: _, transaction_id: _, amount }
+            | RawTransaction::Withdrawal { client_id: _, transaction_id: _, amount }
+            | RawTransaction::PendingDeposit { client_id: _, transaction_id: _, amount } = transaction {
+            debug_assert_eq!(*amount, effective_amount, "{:?} applied with different amount", transaction);
+        }
         if self.locked {
-            eprintln!("No Transaction applied for locked account {:?}", self);
-            return false;
+            warn!(client_id = self.id; "No Transaction applied for locked account {:?}", self);
+            return Err(TransactionError::AccountLocked);
         }
         match transaction  {
-            Transaction::Deposit { client_id: _, transaction_id: _, amount } => {
-                self.available += amount;
-                true
-            },
-            Transaction::Withdrawal { client_id: _, transaction_id: _, amount: _ } => {
-                if self.available >= amount {
-                    self.available -= amount;
-                    true
-                } else {
-                    false
+            RawTransaction::Deposit { client_id: _, transaction_id: _, amount: _ } => self.credit(effective_amount),
+            RawTransaction::Withdrawal { client_id: _, transaction_id: _, amount: _ } => {
+                let available = self.available.checked_sub(effective_amount).ok_or(TransactionError::Overflow)?;
+                if available < -self.overdraft_limit {
+                    return Err(TransactionError::InsufficientFunds);
                 }
+                self.update(|client| {
+                    client.available = available;
+                    Some(())
+                })
             },
-            Transaction::Dispute { client_id: _, transaction_id: _ } => {
-                self.available -= amount;
-                self.held += amount;
-                true
+            RawTransaction::Dispute { client_id: _, transaction_id: _, amount: _ } => self.update(|client| {
+                client.available = client.available.checked_sub(effective_amount)?;
+                client.held = client.held.checked_add(effective_amount)?;
+                Some(())
+            }),
+            RawTransaction::Reslove { client_id: _, transaction_id: _ } => self.update(|client| {
+                let held = effective_amount.checked_sub(client.settle_pending_hold(effective_amount)?)?;
+                let held = client.settle_held_fee(held)?;
+                client.available = client.available.checked_add(held)?;
+                client.held = client.held.checked_sub(held)?;
+                Some(())
+            }),
+            RawTransaction::Chargeback { client_id: _, transaction_id: _ } => self.update(|client| {
+                // disputed amount already moved from available to held, but for what is still pending.
+                let pending = client.settle_pending_hold(effective_amount)?;
+                let held = client.settle_held_fee(effective_amount.checked_sub(pending)?)?;
+                client.held = client.held.checked_sub(held)?;
+                client.available = client.available.checked_sub(pending)?;
+                client.set_locked(true);
+                Some(())
+            }),
+            RawTransaction::PendingDeposit { client_id: _, transaction_id: _, amount: _ } => self.update(|client| {
+                client.held = client.held.checked_add(effective_amount)?;
+                Some(())
+            }),
+            RawTransaction::Capture { client_id: _, transaction_id: _ } => self.update(|client| {
+                let held = client.settle_held_fee(effective_amount)?;
+                client.held = client.held.checked_sub(held)?;
+                client.add_available(held)
+            }),
+            RawTransaction::ChargebackReversal { client_id: _, transaction_id: _ } => {
+                error!(client_id = self.id; "This transaction {:?} should not come in applyTransaction", transaction);
+                Err(TransactionError::NotChargedBack)
             },
-            Transaction::Reslove { client_id: _, transaction_id: _ } => {
-                self.available += amount;
-                self.held += amount;
-                true
+        }
+    }
+
+    /// Applies Dispute, Resolve or Chargeback for disputed transaction, which is DisputedDeposit or DisputedWithdrawal.
+    /// Sign conventions, for disputed amount a
+    /// 1. Deposit - dispute moves a from available to held, resolve moves it back,
+    ///    chargeback takes it out of held, reversing deposit.
+    /// 2. Withdrawal - a has already left available, so dispute puts it in held as claim of client,
+    ///    resolve drops the claim as withdrawal stands, and chargeback moves it from held to available,
+    ///    reversing withdrawal.
+    ///
+    /// Chargeback in either direction locks account.
+    pub fn apply_dispute_transaction(&mut self, transaction: &RawTransaction, disputed: &Transaction) -> Result<(), TransactionError> {
+        let amount = match disputed {
+            Transaction::DisputedDeposit { client_id: _, transaction_id: _, amount, undisputed: _ } => {
+                return self.apply_transaction(transaction, *amount);
             },
-            Transaction::Chargeback { client_id: _, transaction_id: _ } => {
-                self.available -= amount;
-                self.held -= amount;
-                self.set_locked(true);
-                true
+            Transaction::DisputedWithdrawal { client_id: _, transaction_id: _, amount, undisputed: _ } => *amount,
+            _ => {
+                error!(client_id = self.id; "This transaction {:?} is not disputed", disputed);
+                return Err(TransactionError::NotDisputed);
             },
-            Transaction::DisputedDeposit { client_id: _, transaction_id: _, amount: _ } 
-             | Transaction::DisputedWithdrawal { client_id: _, transaction_id: _, amount: _ } => {
-                eprintln!("This transaction {:?} should not come in applyTransaction", transaction);
-                false
+        };
+        if self.locked {
+            warn!(client_id = self.id; "No Transaction applied for locked account {:?}", self);
+            return Err(TransactionError::AccountLocked);
+        }
+        match transaction {
+            RawTransaction::Dispute { client_id: _, transaction_id: _, amount: _ } => self.update(|client| {
+                client.held = client.held.checked_add(amount)?;
+                Some(())
+            }),
+            RawTransaction::Reslove { client_id: _, transaction_id: _ } => self.update(|client| {
+                client.held = client.held.checked_sub(client.settle_held_fee(amount)?)?;
+                Some(())
+            }),
+            RawTransaction::Chargeback { client_id: _, transaction_id: _ } => self.update(|client| {
+                let held = client.settle_held_fee(amount)?;
+                client.held = client.held.checked_sub(held)?;
+                client.available = client.available.checked_add(held)?;
+                client.set_locked(true);
+                Some(())
+            }),
+            _ => {
+                error!(client_id = self.id; "This transaction {:?} should not come in apply_dispute_transaction", transaction);
+                Err(TransactionError::NotDisputed)
             },
         }
     }
 
+    /// Undoes chargeback of charged_back, which is Deposit or Withdrawal as it was before dispute, and unlocks account.
+    /// Charged back deposit comes back to available, charged back withdrawal goes out of available again.
+    pub fn reverse_chargeback(&mut self, charged_back: &Transaction) -> Result<(), TransactionError> {
+        if !matches!(charged_back, Transaction::Deposit { .. } | Transaction::Withdrawal { .. }) {
+            error!(client_id = self.id; "This transaction {:?} can not have been charged back", charged_back);
+            return Err(TransactionError::NotChargedBack);
+        }
+        self.update(|client| {
+            client.available = match charged_back {
+                Transaction::Deposit { client_id: _, transaction_id: _, amount } => client.available.checked_add(*amount)?,
+                Transaction::Withdrawal { client_id: _, transaction_id: _, amount } => client.available.checked_sub(*amount)?,
+                // kind of transaction is checked above.
+                _ => client.available,
+            };
+            client.set_locked(false);
+            Some(())
+        })
+    }
+
     pub fn set_locked(&mut self, locked: bool) {
         self.locked = locked;
     }
 
+    /// Dispute of deposit which holds only what is available, if that is less than amount.
+    /// Shortfall becomes pending hold, to be filled by following deposits.
+    pub fn hold_available(&mut self, amount: Amount) -> Result<(), TransactionError> {
+        self.update(|client| {
+            let held = amount.min(client.available.max(Amount::ZERO));
+            client.available = client.available.checked_sub(held)?;
+            client.held = client.held.checked_add(held)?;
+            client.pending_hold = client.pending_hold.checked_add(amount.checked_sub(held)?)?;
+            Some(())
+        })
+    }
+
+    /// Adds deposited amount, filling pending hold first and rest going to available.
+    /// Unlike apply_transaction it does not check lock, for engine taking deposits to locked accounts.
+    pub fn credit(&mut self, amount: Amount) -> Result<(), TransactionError> {
+        self.update(|client| client.add_available(amount))
+    }
+
+    /// Makes change to copy of client, which takes place of client only if no balance overflowed on the way
+    /// and total still fits in Amount. Change returns None on overflow.
+    fn update(&mut self, change: impl FnOnce(&mut Client) -> Option<()>) -> Result<(), TransactionError> {
+        let mut updated = self.clone();
+        if change(&mut updated).and_then(|()| updated.available.checked_add(updated.held)).is_none() {
+            warn!(client_id = self.id; "No Transaction applied as balance of {:?} would overflow", self);
+            return Err(TransactionError::Overflow);
+        }
+        *self = updated;
+        Ok(())
+    }
+
+    /// Fills pending hold out of amount and adds rest to available.
+    fn add_available(&mut self, amount: Amount) -> Option<()> {
+        let unfilled = self.fill_pending_hold(amount)?;
+        self.available = self.available.checked_add(unfilled)?;
+        Some(())
+    }
+
+    /// Moves as much of amount as pending hold needs to held, returning rest.
+    fn fill_pending_hold(&mut self, amount: Amount) -> Option<Amount> {
+        let filled = amount.min(self.pending_hold);
+        self.pending_hold = self.pending_hold.checked_sub(filled)?;
+        self.held = self.held.checked_add(filled)?;
+        amount.checked_sub(filled)
+    }
+
+    /// Drops up to amount of pending hold, returning how much was dropped.
+    fn settle_pending_hold(&mut self, amount: Amount) -> Option<Amount> {
+        let settled = amount.min(self.pending_hold);
+        self.pending_hold = self.pending_hold.checked_sub(settled)?;
+        Some(settled)
+    }
+
+    /// Amount leaving held, less fee taken out of held earlier, which is settled by as much.
+    /// Interest added to held leaves with the first amount.
+    fn settle_held_fee(&mut self, amount: Amount) -> Option<Amount> {
+        let settled = if self.held_fee.is_positive() { amount.min(self.held_fee) } else { self.held_fee };
+        self.held_fee = self.held_fee.checked_sub(settled)?;
+        amount.checked_sub(settled)
+    }
+
+    /// Takes fee out of held, or adds interest to it for negative fee. Held is then short of disputed and pending
+    /// amounts by fee, so amount leaving held on resolve, chargeback or capture is less by fee, till it is settled.
+    pub fn charge_fee(&mut self, fee: Amount) -> Result<(), TransactionError> {
+        self.update(|client| {
+            client.held = client.held.checked_sub(fee)?;
+            client.held_fee = client.held_fee.checked_add(fee)?;
+            Some(())
+        })
+    }
+
+    /// Fee taken out of held not yet settled, see charge_fee.
+    pub fn held_fee(&self) -> Amount {
+        self.held_fee
+    }
+
+    pub fn pending_hold(&self) -> Amount {
+        self.pending_hold
+    }
+
+    /// Lets withdrawals take available balance down to -limit.
+    pub fn set_overdraft_limit(&mut self, limit: Amount) {
+        self.overdraft_limit = limit;
+    }
+
+    pub fn overdraft_limit(&self) -> Amount {
+        self.overdraft_limit
+    }
+
     pub fn is_locked(&self) -> bool {
         self.locked
     }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn available(&self) -> Amount {
+        self.available
+    }
+
+    pub fn held(&self) -> Amount {
+        self.held
+    }
+
+    pub fn total(&self) -> Amount {
+        self.available + self.held
+    }
+
+    /// Status of account for operator facing reports. Locked account is frozen, even if overdrawn.
+    pub fn status(&self) -> AccountStatus {
+        if self.locked {
+            AccountStatus::Frozen
+        } else if self.available.is_negative() {
+            AccountStatus::Overdrawn
+        } else {
+            AccountStatus::Active
+        }
+    }
+}
+
+/// Human readable state of account, see Client::status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountStatus {
+    Active,
+    /// account is locked.
+    Frozen,
+    /// available balance is below zero, either by overdraft or by dispute of already spent deposit.
+    Overdrawn,
+}
+
+impl Display for AccountStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            AccountStatus::Active => write!(f, "active"),
+            AccountStatus::Frozen => write!(f, "frozen"),
+            AccountStatus::Overdrawn => write!(f, "overdrawn"),
+        }
+    }
+}
+
+/// Change of a client between two snapshots of clients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientDelta {
+    pub client_id: u64,
+    /// available after less available before.
+    pub available: Amount,
+    /// held after less held before.
+    pub held: Amount,
+    /// lock state after, None if it did not change.
+    pub locked: Option<bool>,
+}
+
+/// Changes of clients from before to after, sorted by client id. Client only in after is always reported,
+/// as change from zero balances and unlocked account. Client only in before is not reported.
+pub fn diff_snapshots(before: &[Client], after: &[Client]) -> Vec<ClientDelta> {
+    let before: BTreeMap<u64, &Client> = before.iter().map(|client| (client.id, client)).collect();
+    let mut deltas: Vec<ClientDelta> = after.iter()
+        .filter_map(|client| {
+            let previous = before.get(&client.id).copied();
+            let new_client = Client::new(client.id);
+            let from = previous.unwrap_or(&new_client);
+            let delta = ClientDelta {
+                client_id: client.id,
+                available: client.available - from.available,
+                held: client.held - from.held,
+                locked: (client.locked != from.locked).then_some(client.locked),
+            };
+            let changed = !delta.available.is_zero() || !delta.held.is_zero() || delta.locked.is_some();
+            (previous.is_none() || changed).then_some(delta)
+        })
+        .collect();
+    deltas.sort_by_key(|delta| delta.client_id);
+    deltas
 }
 
 impl Display for Client {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}, {}, {}, {}, {}", self.id, self.available, self.held, self.available + self.held, self.locked)
+    /// Balances are fixed point Amount, so they are written exactly, with at most four decimal places.
+    /// There is no float rounding left to do here.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}, {}, {}, {}, {}", self.id, self.available, self.held, self.total(), self.locked)
+    }
+}
+
+impl Serialize for Client {
+    /// Serialized with computed total, same as csv output. Overdraft limit, pending hold and held fee are only written when set.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Client", 8)?;
+        state.serialize_field("client", &self.id)?;
+        state.serialize_field("available", &self.available)?;
+        state.serialize_field("held", &self.held)?;
+        state.serialize_field("total", &self.total())?;
+        state.serialize_field("locked", &self.locked)?;
+        if self.overdraft_limit.is_zero() {
+            state.skip_field("overdraft_limit")?;
+        } else {
+            state.serialize_field("overdraft_limit", &self.overdraft_limit)?;
+        }
+        if self.pending_hold.is_zero() {
+            state.skip_field("pending_hold")?;
+        } else {
+            state.serialize_field("pending_hold", &self.pending_hold)?;
+        }
+        if self.held_fee.is_zero() {
+            state.skip_field("held_fee")?;
+        } else {
+            state.serialize_field("held_fee", &self.held_fee)?;
+        }
+        state.end()
+    }
+}
+
+/// Serialized form of Client, total is computed so it is ignored while reading.
+#[derive(Deserialize)]
+struct ClientRecord {
+    client: u64,
+    available: Amount,
+    held: Amount,
+    locked: bool,
+    #[serde(default)]
+    overdraft_limit: Amount,
+    #[serde(default)]
+    pending_hold: Amount,
+    #[serde(default)]
+    held_fee: Amount,
+}
+
+impl<'de> Deserialize<'de> for Client {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Client, D::Error> {
+        let record = ClientRecord::deserialize(deserializer)?;
+        Ok(Client {
+            id: record.client,
+            available: record.available,
+            held: record.held,
+            locked: record.locked,
+            overdraft_limit: record.overdraft_limit,
+            pending_hold: record.pending_hold,
+            held_fee: record.held_fee,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn deposit(amount: &str) -> RawTransaction {
+        RawTransaction::new(&format!("deposit, 1, 1, {}", amount))
+    }
+
+    #[test]
+    fn test_output_never_exceeds_four_decimal_places() {
+        let mut client = Client::new(1);
+        let amount = "1.0001".parse().unwrap();
+        client.apply_transaction(&deposit("1.0001"), amount).unwrap();
+        let amount = "2.9999".parse().unwrap();
+        client.apply_transaction(&deposit("2.9999"), amount).unwrap();
+        assert_eq!("1, 4, 0, 4, false", client.to_string());
+
+        let dispute = RawTransaction::new("dispute, 1, 1");
+        client.apply_transaction(&dispute, "4.0001".parse().unwrap()).unwrap();
+        assert_eq!("1, -0.0001, 4.0001, 4, false", client.to_string());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_equal_clients() {
+        let mut client = Client::new(1);
+        client.apply_transaction(&deposit("1.5"), "1.5".parse().unwrap()).unwrap();
+        let mut other = Client::new(1);
+        assert_ne!(client, other);
+        other.apply_transaction(&deposit("1.5"), "1.5".parse().unwrap()).unwrap();
+        assert_eq!(client, other);
+        assert_eq!((1, Amount::from_units(15_000), Amount::ZERO, Amount::from_units(15_000)),
+            (client.id(), client.available(), client.held(), client.total()));
+    }
+
+    #[test]
+    fn test_chargeback_directions() {
+        let amount: Amount = "4".parse().unwrap();
+        let dispute = RawTransaction::new("dispute, 1, 1");
+        let chargeback = RawTransaction::new("chargeback, 1, 1");
+
+        // deposit taken back goes out of held.
+        let mut client = Client::new(1);
+        client.apply_transaction(&deposit("10"), "10".parse().unwrap()).unwrap();
+        let disputed = Transaction::DisputedDeposit { client_id: 1, transaction_id: 1, amount, undisputed: Amount::ZERO };
+        assert_eq!(Ok(()), client.apply_dispute_transaction(&dispute, &disputed));
+        assert_eq!("1, 6, 4, 10, false", client.to_string());
+        assert_eq!(Ok(()), client.apply_dispute_transaction(&chargeback, &disputed));
+        assert_eq!("1, 6, 0, 6, true", client.to_string());
+
+        // withdrawal taken back returns to available.
+        let mut client = Client::new(1);
+        client.apply_transaction(&deposit("10"), "10".parse().unwrap()).unwrap();
+        client.apply_transaction(&RawTransaction::new("withdrawal, 1, 2, 4"), amount).unwrap();
+        let disputed = Transaction::DisputedWithdrawal { client_id: 1, transaction_id: 2, amount, undisputed: Amount::ZERO };
+        assert_eq!(Ok(()), client.apply_dispute_transaction(&dispute, &disputed));
+        assert_eq!("1, 6, 4, 10, false", client.to_string());
+        assert_eq!(Ok(()), client.apply_dispute_transaction(&chargeback, &disputed));
+        assert_eq!("1, 10, 0, 10, true", client.to_string());
+    }
+
+    #[test]
+    fn test_diff_snapshots() {
+        let mut unchanged = Client::new(1);
+        unchanged.apply_transaction(&deposit("1"), "1".parse().unwrap()).unwrap();
+        let mut changed = Client::new(2);
+        changed.apply_transaction(&deposit("5"), "5".parse().unwrap()).unwrap();
+        let before = vec![unchanged.clone(), changed.clone()];
+
+        changed.apply_transaction(&RawTransaction::new("dispute, 2, 1"), "2".parse().unwrap()).unwrap();
+        changed.apply_transaction(&RawTransaction::new("chargeback, 2, 1"), "2".parse().unwrap()).unwrap();
+        let after = vec![Client::new(3), changed, unchanged];
+
+        assert_eq!(vec![
+            ClientDelta { client_id: 2, available: Amount::from_units(-20_000), held: Amount::ZERO, locked: Some(true) },
+            ClientDelta { client_id: 3, available: Amount::ZERO, held: Amount::ZERO, locked: None },
+        ], diff_snapshots(&before, &after));
+        assert!(diff_snapshots(&after, &after).is_empty());
+    }
+
+    #[test]
+    fn test_withdrawal_applies_effective_amount() {
+        let mut client = Client::new(1);
+        client.apply_transaction(&deposit("10"), "10".parse().unwrap()).unwrap();
+        assert_eq!(Ok(()), client.apply_transaction(&RawTransaction::new("withdrawal, 1, 2, 4"), "4".parse().unwrap()));
+        assert_eq!("1, 6, 0, 6, false", client.to_string());
+        // not enough left for withdrawal, which keeps balance untouched.
+        assert_eq!(Err(TransactionError::InsufficientFunds), client.apply_transaction(&RawTransaction::new("withdrawal, 1, 3, 6.0001"), "6.0001".parse().unwrap()));
+        assert_eq!("1, 6, 0, 6, false", client.to_string());
+        assert_eq!(Ok(()), client.apply_transaction(&RawTransaction::new("withdrawal, 1, 4, 6"), "6".parse().unwrap()));
+        assert_eq!("1, 0, 0, 0, false", client.to_string());
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "applied with different amount"))]
+    fn test_withdrawal_with_different_effective_amount() {
+        // effective amount is authoritative, which debug builds catch as a bug of caller.
+        let mut client = Client::new(1);
+        client.apply_transaction(&deposit("10"), "10".parse().unwrap()).unwrap();
+        assert_eq!(Ok(()), client.apply_transaction(&RawTransaction::new("withdrawal, 1, 2, 4"), "3".parse().unwrap()));
+        assert_eq!("1, 7, 0, 7, false", client.to_string());
+    }
+
+    #[test]
+    fn test_status() {
+        let mut client = Client::new(1);
+        client.apply_transaction(&deposit("1"), "1".parse().unwrap()).unwrap();
+        assert_eq!(AccountStatus::Active, client.status());
+        assert_eq!(AccountStatus::Active, Client::new(2).status());
+
+        client.set_overdraft_limit("5".parse().unwrap());
+        client.apply_transaction(&RawTransaction::new("withdrawal, 1, 2, 3"), "3".parse().unwrap()).unwrap();
+        assert_eq!(AccountStatus::Overdrawn, client.status());
+        assert_eq!("overdrawn", client.status().to_string());
+
+        client.set_locked(true);
+        assert_eq!(AccountStatus::Frozen, client.status());
+        assert_eq!("frozen", client.status().to_string());
+    }
+
+    #[test]
+    fn test_overflow_leaves_client_unchanged() {
+        let mut client = Client::new(1);
+        let amount = Amount::from_units(i64::MAX - 1);
+        client.apply_transaction(&deposit("1"), "1".parse().unwrap()).unwrap();
+        client.apply_transaction(&RawTransaction::new("pending, 1, 2, 1"), "1".parse().unwrap()).unwrap();
+        let before = client.clone();
+
+        assert_eq!(Err(TransactionError::Overflow), client.credit(amount));
+        // available alone fits, total of available and held does not.
+        assert_eq!(Err(TransactionError::Overflow), client.credit(Amount::from_units(i64::MAX - 15_000)));
+        assert_eq!(Err(TransactionError::Overflow), client.charge_fee(Amount::from_units(i64::MIN + 1)));
+        assert_eq!(before, client);
+    }
+
+    #[test]
+    fn test_fee_taken_out_of_held() {
+        let mut client = Client::new(1);
+        client.apply_transaction(&RawTransaction::new("pending, 1, 1, 10"), "10".parse().unwrap()).unwrap();
+        client.charge_fee("0.5".parse().unwrap()).unwrap();
+        assert_eq!("1, 0, 9.5, 9.5, false", client.to_string());
+        assert_eq!(Amount::from_units(5_000), client.held_fee());
+
+        // captured amount leaves held less fee.
+        client.apply_transaction(&RawTransaction::new("capture, 1, 1"), "10".parse().unwrap()).unwrap();
+        assert_eq!("1, 9.5, 0, 9.5, false", client.to_string());
+        assert_eq!(Amount::ZERO, client.held_fee());
+    }
+}
@@ -0,0 +1,304 @@
+use alloc::{format, string::ToString};
+use core::{fmt::{self, Display}, iter::Sum, ops::{Add, AddAssign, Neg, Sub, SubAssign}, str::FromStr};
+
+use serde::{de::{self, Visitor}, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Number of decimal places amount keeps.
+pub const SCALE: u32 = 4;
+
+const UNITS_PER_WHOLE: i64 = 10_i64.pow(SCALE);
+
+/// Fixed point money amount, stored as number of ten-thousandths.
+/// Unlike f64, adding 0.1 and 0.2 is exactly 0.3.
+///
+/// Engine, Client and Transaction use Amount directly rather than being generic over amount type.
+/// Any type with exact decimal arithmetic would only repeat what Amount does, while f64 would bring
+/// back rounding errors and make balances drift. Another precise representation belongs inside Amount.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseAmountError {
+    /// not a plain decimal number like `12`, `-1.5` or `.25`.
+    Invalid,
+    /// more than SCALE digits after decimal point.
+    TooManyDecimals,
+    Overflow,
+}
+
+impl Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseAmountError::Invalid => write!(f, "not a decimal number"),
+            ParseAmountError::TooManyDecimals => write!(f, "more than {} decimal places", SCALE),
+            ParseAmountError::Overflow => write!(f, "amount too large"),
+        }
+    }
+}
+
+impl core::error::Error for ParseAmountError {}
+
+/// How digits beyond SCALE decimal places are dropped when parsing amount.
+/// Ties are decided on magnitude, so negative amounts round same as positive ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// half away from zero, `0.00005` becomes `0.0001`.
+    HalfUp,
+    /// half to even last kept digit, aka banker's rounding, `0.00005` becomes `0` and `0.00015` becomes `0.0002`.
+    #[default]
+    HalfEven,
+    /// drop extra digits.
+    Truncate,
+}
+
+impl RoundingMode {
+    /// true if kept units, followed by dropped digits, should go up by one unit.
+    fn rounds_up(self, kept_units: i64, dropped: &str) -> bool {
+        let mut digits = dropped.bytes();
+        let first = digits.next().unwrap_or(b'0');
+        match self {
+            RoundingMode::Truncate => false,
+            RoundingMode::HalfUp => first >= b'5',
+            RoundingMode::HalfEven => match first {
+                b'6'..=b'9' => true,
+                b'5' => digits.any(|digit| digit != b'0') || kept_units % 2 == 1,
+                _ => false,
+            },
+        }
+    }
+}
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    /// Amount made of given number of ten-thousandths.
+    pub const fn from_units(units: i64) -> Amount {
+        Amount(units)
+    }
+
+    pub const fn units(&self) -> i64 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Sum of the two, None if it is beyond what Amount holds.
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    /// Difference of the two, None if it is beyond what Amount holds.
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn abs(&self) -> Amount {
+        Amount(self.0.abs())
+    }
+}
+
+impl Amount {
+    /// Same as parse, but rounds digits beyond SCALE decimal places as per rounding instead of rejecting them.
+    pub fn parse_rounded(input: &str, rounding: RoundingMode) -> Result<Amount, ParseAmountError> {
+        Amount::parse_with(input, Some(rounding))
+    }
+
+    fn parse_with(input: &str, rounding: Option<RoundingMode>) -> Result<Amount, ParseAmountError> {
+        let (negative, unsigned) = match input.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, input.strip_prefix('+').unwrap_or(input)),
+        };
+        let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        if (whole.is_empty() && fraction.is_empty())
+            || !whole.bytes().all(|digit| digit.is_ascii_digit())
+            || !fraction.bytes().all(|digit| digit.is_ascii_digit()) {
+            return Err(ParseAmountError::Invalid);
+        }
+        let (fraction, dropped) = match rounding {
+            Some(_) if fraction.len() > SCALE as usize => fraction.split_at(SCALE as usize),
+            None if fraction.len() > SCALE as usize => return Err(ParseAmountError::TooManyDecimals),
+            _ => (fraction, ""),
+        };
+
+        let whole_units = if whole.is_empty() { 0 } else {
+            whole.parse::<i64>().map_err(|_| ParseAmountError::Overflow)?
+        };
+        let fraction_units = if fraction.is_empty() { 0 } else {
+            fraction.parse::<i64>().unwrap() * 10_i64.pow(SCALE - fraction.len() as u32)
+        };
+        let mut units = whole_units.checked_mul(UNITS_PER_WHOLE)
+            .and_then(|units| units.checked_add(fraction_units))
+            .ok_or(ParseAmountError::Overflow)?;
+        if rounding.is_some_and(|rounding| rounding.rounds_up(units, dropped)) {
+            units = units.checked_add(1).ok_or(ParseAmountError::Overflow)?;
+        }
+        Ok(Amount(if negative { -units } else { units }))
+    }
+}
+
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Amount::parse_with(input, None)
+    }
+}
+
+impl Display for Amount {
+    /// Writes up to SCALE decimal places, trailing zeros trimmed, e.g. `1.5`, `10`, `-0.0001`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let units = self.0.unsigned_abs();
+        let whole = units / UNITS_PER_WHOLE as u64;
+        let fraction = units % UNITS_PER_WHOLE as u64;
+        if fraction == 0 {
+            return write!(f, "{}{}", sign, whole);
+        }
+        let fraction = format!("{:0width$}", fraction, width = SCALE as usize);
+        write!(f, "{}{}.{}", sign, whole, fraction.trim_end_matches('0'))
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, rhs: Amount) -> Amount {
+        Amount(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, rhs: Amount) -> Amount {
+        Amount(self.0 - rhs.0)
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, rhs: Amount) {
+        self.0 += rhs.0;
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, rhs: Amount) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+
+    fn neg(self) -> Amount {
+        Amount(-self.0)
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::ZERO, Add::add)
+    }
+}
+
+impl Serialize for Amount {
+    /// Serialized as decimal string same as Display, e.g. `"1.5"`, so that it reads back exactly,
+    /// which would not be the case going through f64.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+struct AmountVisitor;
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "decimal amount with at most {} decimal places", SCALE)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Amount, E> {
+        value.trim().parse().map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Amount, E> {
+        self.visit_str(&value.to_string())
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Amount, E> {
+        self.visit_str(&value.to_string())
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Amount, E> {
+        self.visit_str(&value.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Amount, D::Error> {
+        deserializer.deserialize_str(AmountVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn amount(input: &str) -> Amount {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Amount::from_units(15_000), amount("1.5"));
+        assert_eq!(Amount::from_units(-2_500), amount("-.25"));
+        assert_eq!(Amount::from_units(10_000), amount("1."));
+        assert_eq!(Amount::from_units(1), amount("0.0001"));
+        assert_eq!(Amount::ZERO, amount("-0.0"));
+        assert_eq!(Err(ParseAmountError::TooManyDecimals), "1.00001".parse::<Amount>());
+        for invalid in ["", ".", "-", "abc", "1.2.3", "1e3", "NaN", "inf", " 1"] {
+            assert_eq!(Err(ParseAmountError::Invalid), invalid.parse::<Amount>(), "{}", invalid);
+        }
+        assert_eq!(Err(ParseAmountError::Overflow), "99999999999999999999".parse::<Amount>());
+    }
+
+    #[test]
+    fn test_rounding_modes() {
+        let rounded = |input: &str, rounding| Amount::parse_rounded(input, rounding).unwrap().to_string();
+        assert_eq!("0.5001", rounded("0.50005", RoundingMode::HalfUp));
+        assert_eq!("0.5", rounded("0.50005", RoundingMode::HalfEven));
+        assert_eq!("0.5", rounded("0.50005", RoundingMode::Truncate));
+
+        assert_eq!("0.5002", rounded("0.50015", RoundingMode::HalfEven));
+        assert_eq!("0.5001", rounded("0.500051", RoundingMode::HalfEven));
+        assert_eq!("-0.5001", rounded("-0.50005", RoundingMode::HalfUp));
+        assert_eq!("0.5", rounded("0.50004999", RoundingMode::HalfUp));
+        assert_eq!("1.5", rounded("1.5", RoundingMode::Truncate));
+        assert_eq!(RoundingMode::HalfEven, RoundingMode::default());
+    }
+
+    #[test]
+    fn test_display_trims_trailing_zeros() {
+        assert_eq!("10", amount("10.0000").to_string());
+        assert_eq!("1.5", amount("1.50").to_string());
+        assert_eq!("-0.0001", amount("-0.0001").to_string());
+        assert_eq!("0", Amount::ZERO.to_string());
+    }
+
+    #[test]
+    fn test_no_rounding_drift() {
+        assert_eq!(amount("0.3"), amount("0.1") + amount("0.2"));
+        assert_eq!("0.3", (amount("0.1") + amount("0.2")).to_string());
+    }
+}
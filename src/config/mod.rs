@@ -0,0 +1,64 @@
+use std::{num::NonZeroUsize, thread};
+
+use crate::amount::RoundingMode;
+
+/// What to do when disputed deposit is more than available balance of client,
+/// e.g. client has already withdrawn the deposited funds.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DisputePolicy {
+    /// move disputed amount to held anyway, available goes negative.
+    #[default]
+    AllowNegativeAvailable,
+    /// reject dispute, leaving balances as they are.
+    RejectInsufficientAvailable,
+    /// hold only what is available, recording shortfall as pending hold of client,
+    /// which following deposits fill before adding to available.
+    HoldAvailable,
+}
+
+/// Policies with which InMemoryTransactionEngine is built.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineConfig {
+    /// maximum number of distinct clients engine will keep. None means no limit.
+    pub max_clients: Option<usize>,
+    /// keep referring transactions arriving before their referred transaction, instead of skipping them.
+    pub defer_unknown_references: bool,
+    /// number of threads used by parallel processing.
+    pub workers: usize,
+    pub dispute_policy: DisputePolicy,
+    /// skip deposit, withdrawal or pending reusing id of transaction already with engine.
+    /// When false such transaction is applied and replaces earlier one as target of dispute.
+    pub reject_duplicate_tx: bool,
+    /// reject transaction whose timestamp is before last applied timestamp of its client,
+    /// instead of only logging it and applying in file order.
+    pub strict_ordering: bool,
+    /// transactions kept for locked accounts, beyond which oldest ones are dropped.
+    pub max_blocked_transactions: usize,
+    /// reject transactions of client id zero, which some feeds use as sentinel. Zero is a valid id by default.
+    pub reject_zero_client_id: bool,
+    /// reject transactions with transaction id zero, which some feeds use as sentinel. Zero is a valid id by default.
+    pub reject_zero_transaction_id: bool,
+    /// apply deposits to locked accounts, while every other transaction of such account stays blocked.
+    pub allow_deposits_when_locked: bool,
+    /// how amounts are rounded to four decimal places, both those engine works out itself, e.g. fees of accrue_held_fees,
+    /// and amounts of rows processor reads for engine, which take it over ParseOptions::rounding.
+    pub rounding: RoundingMode,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            max_clients: None,
+            defer_unknown_references: false,
+            workers: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            dispute_policy: DisputePolicy::default(),
+            reject_duplicate_tx: true,
+            strict_ordering: false,
+            max_blocked_transactions: 10_000,
+            reject_zero_client_id: false,
+            reject_zero_transaction_id: false,
+            allow_deposits_when_locked: false,
+            rounding: RoundingMode::default(),
+        }
+    }
+}
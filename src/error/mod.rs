@@ -0,0 +1,73 @@
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+/// Reasons for which engine can refuse a transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransactionError {
+    /// transaction would create a new client while engine already holds configured max clients.
+    ClientLimitExceeded,
+    /// no transaction of client matches what was looked up.
+    NoMatchingTransaction,
+    /// more than one transaction of client matches what was looked up, ids of all of them are carried.
+    AmbiguousMatch(Vec<u32>),
+    /// engines being merged both hold these clients, ids of all of them are carried.
+    ClientCollision(Vec<u64>),
+    /// account of client is locked, transaction is kept with blocked transactions.
+    AccountLocked,
+    /// transaction id is already used by another transaction.
+    DuplicateTransaction,
+    /// withdrawal more than available, or dispute of more than available with RejectInsufficientAvailable.
+    InsufficientFunds,
+    /// referred transaction belongs to another client.
+    WrongClient,
+    /// referred transaction is already disputed, or is not a deposit or withdrawal.
+    NotDisputable,
+    /// resolve or chargeback of transaction which is not disputed.
+    NotDisputed,
+    /// capture of transaction which is not pending.
+    NotPending,
+    /// reversal of transaction which is not charged back.
+    NotChargedBack,
+    /// timestamp is before last applied transaction of client, with strict ordering.
+    OutOfOrder,
+    /// referred transaction is not yet with engine, transaction is kept aside till it arrives.
+    Deferred,
+    /// referred transaction is not with engine.
+    TransactionNotFound,
+    /// referred transaction was resolved or charged back, and is no longer with engine.
+    AlreadyFinalized,
+    /// client or transaction id is zero, while engine rejects such id.
+    ZeroId,
+    /// balance of client, or total of engine, would go beyond what Amount holds.
+    Overflow,
+    /// engine being merged into keeps replay log, while other engine does not.
+    MissingReplayLog,
+}
+
+impl Display for TransactionError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TransactionError::ClientLimitExceeded => write!(f, "client limit exceeded"),
+            TransactionError::NoMatchingTransaction => write!(f, "no matching transaction"),
+            TransactionError::AmbiguousMatch(transaction_ids) => write!(f, "ambiguous match among {:?}", transaction_ids),
+            TransactionError::ClientCollision(client_ids) => write!(f, "clients {:?} held by both engines", client_ids),
+            TransactionError::AccountLocked => write!(f, "account locked"),
+            TransactionError::DuplicateTransaction => write!(f, "duplicate transaction id"),
+            TransactionError::InsufficientFunds => write!(f, "insufficient funds"),
+            TransactionError::WrongClient => write!(f, "referred transaction belongs to another client"),
+            TransactionError::NotDisputable => write!(f, "referred transaction can not be disputed"),
+            TransactionError::NotDisputed => write!(f, "referred transaction is not disputed"),
+            TransactionError::NotPending => write!(f, "referred transaction is not pending"),
+            TransactionError::NotChargedBack => write!(f, "referred transaction is not charged back"),
+            TransactionError::OutOfOrder => write!(f, "timestamp out of order"),
+            TransactionError::Deferred => write!(f, "deferred till referred transaction arrives"),
+            TransactionError::TransactionNotFound => write!(f, "referred transaction not found"),
+            TransactionError::AlreadyFinalized => write!(f, "referred transaction already finalized"),
+            TransactionError::ZeroId => write!(f, "zero id"),
+            TransactionError::Overflow => write!(f, "balance overflow"),
+            TransactionError::MissingReplayLog => write!(f, "other engine has no replay log"),
+        }
+    }
+}
+
+impl core::error::Error for TransactionError {}
@@ -1,199 +1,2441 @@
-use std::{sync::Mutex, collections::HashMap};
+//! Payment engine applying deposits, withdrawals and disputes to client accounts.
+//!
+//! With default `std` feature everything is built: InMemoryTransactionEngine and its builder and config,
+//! csv reading of transactions, processor, report, snapshot, metrics, parallel processing and cli.
+//!
+//! Without it crate is `no_std` and needs only `alloc`. Then only `transaction` (parsing of single rows,
+//! not csv reader), `account`, `amount`, `error` and TransactionEngine trait with its outcome types
+//! are built, for engines running where there is no file system, e.g. embedded or wasm.
+//!
+//! `tokio` feature adds `stream::process_stream`, applying transactions of an async stream.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+use std::{sync::Mutex, collections::{BTreeMap, HashMap, HashSet, VecDeque}, io::{self, Write}};
+
+use alloc::{boxed::Box, vec::Vec};
+
+use chrono::{DateTime, Utc};
+#[cfg(feature = "std")]
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
 
 use account::Client;
-use transaction::Transaction;
+#[cfg(feature = "std")]
+use amount::Amount;
+use amount::RoundingMode;
+#[cfg(feature = "std")]
+use config::{DisputePolicy, EngineConfig};
+use error::TransactionError;
+use transaction::RawTransaction;
+#[cfg(feature = "std")]
+use transaction::{Transaction, TransactionType};
 
 pub mod transaction;
 pub mod account;
+pub mod amount;
+#[cfg(feature = "std")]
+pub mod config;
+pub mod error;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod parallel;
+#[cfg(feature = "std")]
+pub mod processor;
+#[cfg(feature = "std")]
+pub mod report;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "tokio")]
+pub mod stream;
 
 pub trait TransactionEngine {
-    fn add_transaction(& mut self, transaction: Transaction) -> bool;
+    /// Ok if transaction got applied, otherwise reason it was not.
+    fn add_transaction(&self, transaction: RawTransaction) -> Result<(), TransactionError>;
+    /// Same as add_transaction, for transaction which came with timestamp. Engines not keeping
+    /// track of time apply it same as add_transaction.
+    fn add_transaction_at(&self, transaction: RawTransaction, timestamp: Option<DateTime<Utc>>) -> Result<(), TransactionError> {
+        let _ = timestamp;
+        self.add_transaction(transaction)
+    }
+    /// Applies all transactions of batch, in order, or none of them. On first transaction not applied,
+    /// engine is rolled back to where it was before batch, and index of that transaction is returned with reason.
+    /// Unlike add_transaction it takes engine exclusively, so no other transaction lands in between to be rolled back with batch.
+    fn add_transactions_atomic(&mut self, batch: Vec<RawTransaction>) -> Result<(), (usize, TransactionError)>;
     fn snap_shot_clients(&self) -> Vec<Client>;
+    /// Same as snap_shot_clients, ordered by client id, so output of same input is same run to run.
+    fn snap_shot_clients_sorted(&self) -> Vec<Client> {
+        let mut clients = self.snap_shot_clients();
+        clients.sort_unstable_by_key(Client::id);
+        clients
+    }
+    /// Copy of single client, without snapshotting every client.
+    fn get_client(&self, client_id: u64) -> Option<Client>;
+    /// Calls f for every client, without cloning clients or collecting them.
+    /// Clients stay locked till f has been called for every one of them, so f should be quick
+    /// and must not call back into engine.
+    fn for_each_client(&self, f: &mut dyn FnMut(&Client));
+    /// How amounts with more than four decimal places are rounded when rows are read for this engine, see processor.
+    /// None leaves it to ParseOptions::rounding.
+    fn rounding(&self) -> Option<RoundingMode> {
+        None
+    }
 }
 
-pub struct InMemoryTransactionEngine {
-    tranasctions: Mutex<HashMap<u32, Transaction>>,
-    clients: Mutex<HashMap<u16, Client>>,
+/// Result of applying a single transaction to engine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Applied,
+    Rejected,
+}
+
+/// Applies transactions in given order and returns outcome for each of them, at same index.
+pub fn process_all(engine: &impl TransactionEngine, txns: Vec<RawTransaction>) -> Vec<Outcome> {
+    txns.into_iter()
+        .map(|transaction| if engine.add_transaction(transaction).is_ok() { Outcome::Applied } else { Outcome::Rejected })
+        .collect()
+}
+
+/// What exactly happened to a transaction given to engine, as told to observer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TransactionOutcome {
+    Applied,
+    /// referred transaction is not yet with engine, transaction is kept aside till it arrives.
+    Deferred,
+    /// account of client is locked, transaction is kept with blocked transactions.
+    RejectedLocked,
+    /// transaction id is already used by another transaction.
+    RejectedDuplicate,
+    /// transaction would create new client beyond configured max clients.
+    RejectedClientLimit,
+    /// withdrawal more than available, or dispute of more than available with RejectInsufficientAvailable.
+    RejectedInsufficientFunds,
+    /// referred transaction belongs to another client.
+    RejectedWrongClient,
+    /// referred transaction is already disputed, or is not a deposit or withdrawal.
+    RejectedNotDisputable,
+    /// resolve or chargeback of transaction which is not disputed.
+    RejectedNotDisputed,
+    /// capture of transaction which is not pending.
+    RejectedNotPending,
+    /// reversal of transaction which is not charged back.
+    RejectedNotChargedBack,
+    /// timestamp is before last applied transaction of client, with strict ordering.
+    RejectedOutOfOrder,
+    /// client or transaction id is zero, while config rejects such id.
+    RejectedZeroId,
+    /// referred transaction is not with engine.
+    NotFound,
+    /// referred transaction was resolved or charged back, and is no longer with engine.
+    RejectedFinalized,
+    /// balance of client, or total of engine, would go beyond what Amount holds.
+    RejectedOverflow,
+}
+
+impl TransactionOutcome {
+    /// Ok for Applied, otherwise TransactionError for same reason.
+    pub fn into_result(self) -> Result<(), TransactionError> {
+        match self {
+            TransactionOutcome::Applied => Ok(()),
+            TransactionOutcome::Deferred => Err(TransactionError::Deferred),
+            TransactionOutcome::RejectedLocked => Err(TransactionError::AccountLocked),
+            TransactionOutcome::RejectedDuplicate => Err(TransactionError::DuplicateTransaction),
+            TransactionOutcome::RejectedClientLimit => Err(TransactionError::ClientLimitExceeded),
+            TransactionOutcome::RejectedInsufficientFunds => Err(TransactionError::InsufficientFunds),
+            TransactionOutcome::RejectedWrongClient => Err(TransactionError::WrongClient),
+            TransactionOutcome::RejectedNotDisputable => Err(TransactionError::NotDisputable),
+            TransactionOutcome::RejectedNotDisputed => Err(TransactionError::NotDisputed),
+            TransactionOutcome::RejectedNotPending => Err(TransactionError::NotPending),
+            TransactionOutcome::RejectedNotChargedBack => Err(TransactionError::NotChargedBack),
+            TransactionOutcome::RejectedOutOfOrder => Err(TransactionError::OutOfOrder),
+            TransactionOutcome::RejectedZeroId => Err(TransactionError::ZeroId),
+            TransactionOutcome::NotFound => Err(TransactionError::TransactionNotFound),
+            TransactionOutcome::RejectedFinalized => Err(TransactionError::AlreadyFinalized),
+            TransactionOutcome::RejectedOverflow => Err(TransactionError::Overflow),
+        }
+    }
+
+    /// Rejection for reason client refused transaction, see Client::apply_transaction.
+    #[cfg(feature = "std")]
+    fn refused(err: TransactionError) -> TransactionOutcome {
+        match err {
+            TransactionError::AccountLocked => TransactionOutcome::RejectedLocked,
+            TransactionError::NotDisputed => TransactionOutcome::RejectedNotDisputed,
+            TransactionError::NotChargedBack => TransactionOutcome::RejectedNotChargedBack,
+            TransactionError::Overflow => TransactionOutcome::RejectedOverflow,
+            // withdrawal beyond available and overdraft is the only other refusal of client.
+            _ => TransactionOutcome::RejectedInsufficientFunds,
+        }
+    }
+}
+
+/// Called with every transaction engine gets and its outcome.
+pub type Observer = Box<dyn FnMut(&RawTransaction, TransactionOutcome) + Send>;
+
+/// Sizes of what engine is holding, for monitoring.
+#[cfg(feature = "std")]
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EngineStats {
+    pub active_transactions: usize,
+    pub blocked_transactions: usize,
+    pub finalized_transactions: usize,
+    pub total_clients: usize,
+    pub locked_clients: usize,
+}
+
+/// Everything engine keeps apart from clients and config. It is all behind one lock, always taken before lock of
+/// clients, so that a transaction updates both as a single step and threads sharing engine see it whole.
+#[cfg(feature = "std")]
+#[derive(Default)]
+struct EngineState {
+    tranasctions: HashMap<u32, Transaction>,
     // these are transactions applied after client account has been locked.
     // They do not play any role in client account but kept for house keeping,
     // so that can be applied once account in unlocked and audited.
-    // not locking it for now as it is used single place for now and that can be accomodated by tranasctions lock.
-    blocked_transactions: Vec<Transaction>,
+    // Only latest config.max_blocked_transactions of them are kept. Keyed by arrival sequence, so that
+    // transactions of one client can be taken out without shifting the others.
+    blocked_transactions: BTreeMap<u64, RawTransaction>,
+    // sequence next blocked transaction gets.
+    next_blocked_sequence: u64,
+    // sequences of blocked transactions, per client, oldest first.
+    client_blocked: HashMap<u64, VecDeque<u64>>,
+    // ids of transactions in tranasctions, per client, so that transactions of a client are found without scanning all.
+    client_transactions: HashMap<u64, HashSet<u32>>,
     // once transaction is resolved, it comes here for historical reference.
-    // not locking it for now as it is used single place for now and that can be accomodated by tranasctions lock.
     finalized_transactions: Vec<Transaction>,
+    // positions in finalized_transactions, per client, in order they were finalized.
+    client_finalized: HashMap<u64, Vec<usize>>,
+    // ids of finalized transactions which were charged back, and not reversed since.
+    charged_back: HashSet<u32>,
+    // ids of transactions resolved or charged back as a whole, so that later reference to them is told apart
+    // from reference to transaction engine never saw.
+    resolved_tx_ids: HashSet<u32>,
+    // client as it was right before chargeback locked it, latest lock per client.
+    lock_snapshots: HashMap<u64, Client>,
+    // money that should be with clients as per accepted transactions, i.e. deposits - withdrawals,
+    // adjusted for chargebacks and claims held against disputed withdrawals. Used to cross check arithmetic of client balances.
+    ledger_total: Amount,
+    // referring transactions which arrived before transaction they refer to.
+    deferred_transactions: Vec<RawTransaction>,
+    // number of applied transactions per client and transaction type.
+    type_counts: HashMap<u64, HashMap<TransactionType, usize>>,
+    // number of transactions not applied, per outcome. Deferred transactions are not counted till they get an outcome.
+    rejection_counts: HashMap<TransactionOutcome, usize>,
+    // latest timestamp among applied transactions of client, for those that came with one.
+    last_timestamps: HashMap<u64, DateTime<Utc>>,
+    // fees charged on held funds of client by accrue_held_fees, all accruals added up.
+    held_fees: HashMap<u64, Amount>,
+    observer: Option<Observer>,
+    // applied transactions in order they were applied, once enable_replay_log is called.
+    replay_log: Option<Vec<RawTransaction>>,
+}
+
+#[cfg(feature = "std")]
+impl EngineState {
+    /// Keeps transaction which arrived for locked account, after those blocked so far.
+    /// Oldest blocked transaction is dropped once max_blocked are kept, and nothing is kept with max_blocked of 0.
+    fn block(&mut self, transaction: RawTransaction, max_blocked: usize) {
+        if self.blocked_transactions.len() >= max_blocked {
+            if let Some(dropped) = self.drop_oldest_blocked() {
+                warn!(client_id = dropped.client_id(), transaction_id = dropped.transaction_id(); "Dropping oldest blocked transaction {:?} as {} are kept", &dropped, max_blocked);
+            }
+        }
+        if max_blocked == 0 {
+            return;
+        }
+        let sequence = self.next_blocked_sequence;
+        self.next_blocked_sequence += 1;
+        self.client_blocked.entry(transaction.client_id()).or_default().push_back(sequence);
+        self.blocked_transactions.insert(sequence, transaction);
+    }
+
+    /// Drops oldest blocked transaction, of whichever client, to make room for new one.
+    fn drop_oldest_blocked(&mut self) -> Option<RawTransaction> {
+        let (sequence, dropped) = self.blocked_transactions.pop_first()?;
+        if let Some(sequences) = self.client_blocked.get_mut(&dropped.client_id()) {
+            // oldest of all is oldest of its client as well.
+            debug_assert_eq!(Some(sequence), sequences.front().copied());
+            sequences.pop_front();
+            if sequences.is_empty() {
+                self.client_blocked.remove(&dropped.client_id());
+            }
+        }
+        Some(dropped)
+    }
+
+    /// Ids of every transaction engine holds or has finalized.
+    fn transaction_ids(&self) -> HashSet<u32> {
+        self.tranasctions.keys().copied()
+            .chain(self.finalized_transactions.iter().map(Transaction::transaction_id))
+            .chain(self.charged_back.iter().copied())
+            .chain(self.resolved_tx_ids.iter().copied())
+            .collect()
+    }
+
+    /// Takes out blocked transactions of client, oldest first.
+    fn take_blocked(&mut self, client_id: u64) -> Vec<RawTransaction> {
+        self.client_blocked.remove(&client_id).into_iter().flatten()
+            .filter_map(|sequence| self.blocked_transactions.remove(&sequence))
+            .collect()
+    }
+
+    /// Keeps transaction resolved or charged back, for audit.
+    fn finalize(&mut self, transaction: Transaction) {
+        self.client_finalized.entry(transaction.client_id()).or_default().push(self.finalized_transactions.len());
+        self.finalized_transactions.push(transaction);
+    }
 }
 
+#[cfg(feature = "std")]
+pub struct InMemoryTransactionEngine {
+    state: Mutex<EngineState>,
+    clients: Mutex<HashMap<u64, Client>>,
+    config: EngineConfig,
+}
+
+#[cfg(feature = "std")]
 impl InMemoryTransactionEngine {
     pub fn new() -> Self {
+        InMemoryTransactionEngineBuilder::new().build()
+    }
+
+    pub(crate) fn with_config(config: EngineConfig) -> Self {
         InMemoryTransactionEngine {
-            tranasctions: Mutex::new(HashMap::new()),
+            state: Mutex::new(EngineState::default()),
             clients: Mutex::new(HashMap::new()),
-            blocked_transactions: Vec::new(),
-            finalized_transactions: Vec::new(),
-         }
+            config,
+        }
     }
-}
-impl TransactionEngine for InMemoryTransactionEngine {
-    /// This method add transaction to Engine.
-    /// Following are rules
-    /// 1. Client Account has to be not in locked state. It will do nothing if account is locked.
-    /// 2. Deposit will simply increase available balance.
-    /// 3. Withdraw will check if account has more available balance than withdrawal amount, it will let transaction go.
-    /// 4. Only Transaction that can be disputed are Deposit or Withdrawal.
-    /// 5. Only Disputed Transaction can be 
-    ///     a. Resolved - once resolved, transaction is removed from tranasctions,
-    ///     otherwise one can keep disputing same transaction and gain system.
-    ///     b. Chargeback - once applied, transaction is removed from tranasctions,
-    ///     also client account is locked and no further transaction is allowed on client.
-    fn add_transaction(&mut self, transaction_to_add: Transaction) -> bool {
-        let mut transactions = self.tranasctions.lock().unwrap();
+
+    /// Engine with default config, populated from every valid line of reader. See processor::process.
+    pub fn from_reader<R: std::io::BufRead>(reader: R) -> Self {
+        processor::process(reader).0
+    }
+
+    /// Registers f to be called with every transaction engine gets, including deferred ones once
+    /// they are applied, and its outcome. Replaces earlier observer.
+    pub fn set_observer<F>(&mut self, f: F)
+    where
+        F: FnMut(&RawTransaction, TransactionOutcome) + Send + 'static,
+    {
+        self.state.get_mut().unwrap().observer = Some(Box::new(f));
+    }
+
+    /// Starts recording every transaction applied from now on, see replay_log.
+    pub fn enable_replay_log(&mut self) {
+        self.state.get_mut().unwrap().replay_log.get_or_insert_with(Vec::new);
+    }
+
+    /// Transactions applied since enable_replay_log, in order they were applied. Deferred transaction comes
+    /// once it is applied, after transaction it refers to. Empty if replay log is not enabled.
+    pub fn replay_log(&self) -> Vec<RawTransaction> {
+        self.state.lock().unwrap().replay_log.clone().unwrap_or_default()
+    }
+
+    /// Policies engine is running with, as resolved by builder.
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Unlocks account of client and replays transactions blocked while it was locked, in their arrival order.
+    /// Returns blocked transactions which still could not be applied. If a replayed chargeback locks account
+    /// again, following transactions of client go back to blocked transactions as well.
+    /// Engine is held till replay is done, so new transactions of client come after blocked ones.
+    pub fn unlock_client(&self, client_id: u64) -> Vec<RawTransaction> {
+        let mut state = self.state.lock().unwrap();
+        let mut clients = self.clients.lock().unwrap();
+        match clients.get_mut(&client_id) {
+            Some(client) => client.set_locked(false),
+            None => return Vec::new(),
+        }
+        self.replay_blocked(&mut state, &mut clients, client_id)
+    }
+
+    /// Applies blocked transactions of client once its account is unlocked, returning those which still could not be applied.
+    fn replay_blocked(&self, state: &mut EngineState, clients: &mut HashMap<u64, Client>, client_id: u64) -> Vec<RawTransaction> {
+        state.take_blocked(client_id).into_iter()
+            .filter(|blocked| self.add_locked(state, clients, blocked.clone()).is_err())
+            .collect()
+    }
+
+    pub fn stats(&self) -> EngineStats {
+        let state = self.state.lock().unwrap();
+        let clients = self.clients.lock().unwrap();
+        EngineStats {
+            active_transactions: state.tranasctions.len(),
+            blocked_transactions: state.blocked_transactions.len(),
+            finalized_transactions: state.finalized_transactions.len(),
+            total_clients: clients.len(),
+            locked_clients: clients.values().filter(|client| client.is_locked()).count(),
+        }
+    }
+
+    /// Clears every transaction and client, returning engine to state it was built in.
+    /// Config and observer are kept.
+    pub fn reset(&mut self) {
+        let state = self.state.get_mut().unwrap();
+        let observer = state.observer.take();
+        let replay_log = state.replay_log.as_ref().map(|_| Vec::new());
+        *state = EngineState { observer, replay_log, ..EngineState::default() };
+        self.clients.get_mut().unwrap().clear();
+    }
+
+    /// Ids of clients whose account is locked, in ascending order.
+    pub fn locked_clients(&self) -> Vec<u64> {
+        let clients = self.clients.lock().unwrap();
+        let mut locked: Vec<u64> = clients.values()
+            .filter(|client| client.is_locked())
+            .map(Client::id)
+            .collect();
+        locked.sort_unstable();
+        locked
+    }
+
+    /// Client as it was right before its account got locked by chargeback, for audit of locked accounts.
+    /// If account was locked more than once, it is as of latest lock.
+    pub fn lock_snapshot(&self, client_id: u64) -> Option<Client> {
+        let state = self.state.lock().unwrap();
+        state.lock_snapshots.get(&client_id).cloned()
+    }
+
+    /// Number of transactions of each type applied for client. Rejected and blocked transactions are not counted.
+    pub fn client_type_counts(&self, client_id: u64) -> HashMap<TransactionType, usize> {
+        let state = self.state.lock().unwrap();
+        state.type_counts.get(&client_id).cloned().unwrap_or_default()
+    }
+
+    /// Lets withdrawals of client take its available balance down to -limit. Client is created if engine
+    /// does not have it yet, as long as it is within max clients.
+    pub fn set_overdraft_limit(&self, client_id: u64, limit: Amount) -> Result<(), TransactionError> {
+        let _state = self.state.lock().unwrap();
+        let mut clients = self.clients.lock().unwrap();
+        if !clients.contains_key(&client_id) {
+            self.can_add_client(&clients)?;
+        }
+        clients.entry(client_id).or_insert_with(|| Client::new(client_id)).set_overdraft_limit(limit);
+        Ok(())
+    }
+
+    /// Resolved and charged back transactions of client, in order they were finalized.
+    pub fn audit_log(&self, client_id: u64) -> Vec<Transaction> {
+        let state = self.state.lock().unwrap();
+        state.client_finalized.get(&client_id).into_iter().flatten()
+            .map(|position| state.finalized_transactions[*position].clone())
+            .collect()
+    }
+
+    /// Disputed transactions, of all clients, which are yet to be resolved or charged back, ordered by transaction id.
+    /// This scans all transactions, so is meant for occasional review of open disputes.
+    pub fn open_disputes(&self) -> Vec<Transaction> {
+        let state = self.state.lock().unwrap();
+        let mut disputed: Vec<Transaction> = state.tranasctions.values()
+            .filter(|transaction| transaction.is_disputed())
+            .cloned()
+            .collect();
+        disputed.sort_unstable_by_key(Transaction::transaction_id);
+        disputed
+    }
+
+    /// Transactions of client contributing to its held balance, with amount each holds, ordered by transaction id.
+    /// These are open disputes, for their disputed part, and pending deposits not yet captured, and add up to held of client.
+    /// Only exception is dispute held for lack of funds under DisputePolicy::HoldAvailable, which is listed with
+    /// full disputed amount while client holds pending_hold less.
+    pub fn held_breakdown(&self, client_id: u64) -> Vec<(u32, Amount)> {
+        let state = self.state.lock().unwrap();
+        let mut held: Vec<(u32, Amount)> = state.client_transactions.get(&client_id).into_iter().flatten()
+            .filter_map(|transaction_id| match state.tranasctions.get(transaction_id) {
+                Some(Transaction::DisputedDeposit { amount, .. })
+                | Some(Transaction::DisputedWithdrawal { amount, .. })
+                | Some(Transaction::PendingDeposit { amount, .. }) => Some((*transaction_id, *amount)),
+                _ => None,
+            })
+            .collect();
+        held.sort_unstable_by_key(|(transaction_id, _)| *transaction_id);
+        held
+    }
+
+    /// Transactions which arrived for locked accounts, oldest first, for audit.
+    /// Only latest config.max_blocked_transactions of them are kept.
+    pub fn blocked_transactions(&self) -> Vec<RawTransaction> {
+        let state = self.state.lock().unwrap();
+        state.blocked_transactions.values().cloned().collect()
+    }
+
+    /// Same as blocked_transactions, only those of given client.
+    pub fn blocked_transactions_for(&self, client_id: u64) -> Vec<RawTransaction> {
+        let state = self.state.lock().unwrap();
+        state.client_blocked.get(&client_id).into_iter().flatten()
+            .map(|sequence| state.blocked_transactions[sequence].clone())
+            .collect()
+    }
+
+    /// Writes transactions which arrived for locked accounts, as csv.
+    pub fn write_blocked_csv<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let state = self.state.lock().unwrap();
+        report::write_transactions(state.blocked_transactions.values(), w)
+    }
+
+    /// Writes resolved and charged back transactions, as csv.
+    pub fn write_finalized_csv<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let state = self.state.lock().unwrap();
+        report::write_transactions(&state.finalized_transactions, w)
+    }
+
+    /// Disputes transaction of client having given amount, for cases where transaction id is not known.
+    /// Only undisputed deposit or withdrawal is considered. Engine does not keep arrival order of transactions,
+    /// so more than one match can not be resolved and is reported as AmbiguousMatch.
+    /// Returns id of disputed transaction.
+    pub fn dispute_by_amount(&self, client_id: u64, amount: Amount) -> Result<u32, TransactionError> {
+        let mut matching: Vec<u32> = {
+            let state = self.state.lock().unwrap();
+            state.client_transactions.get(&client_id).into_iter().flatten()
+                .filter_map(|transaction_id| match state.tranasctions.get(transaction_id) {
+                    Some(Transaction::Deposit { amount: existing, .. })
+                    | Some(Transaction::Withdrawal { amount: existing, .. }) if *existing == amount => Some(*transaction_id),
+                    _ => None,
+                })
+                .collect()
+        };
+        matching.sort_unstable();
+
+        match matching.as_slice() {
+            [] => Err(TransactionError::NoMatchingTransaction),
+            [transaction_id] => {
+                let transaction_id = *transaction_id;
+                self.add_transaction(RawTransaction::Dispute { client_id, transaction_id, amount: None })?;
+                Ok(transaction_id)
+            },
+            _ => Err(TransactionError::AmbiguousMatch(matching)),
+        }
+    }
+
+    /// Folds everything other engine holds into this one, e.g. to report on shards each owning a subset of clients.
+    /// Config and observer of this engine are kept, and observer is not told about merged transactions.
+    /// Engines are expected to hold disjoint clients and transaction ids, finalized ones included. Otherwise nothing
+    /// is merged, and ClientCollision lists clients held by both, or DuplicateTransaction tells transaction ids collide.
+    /// Likewise nothing is merged with MissingReplayLog if this engine keeps replay log while other does not, or with
+    /// Overflow if total of both does not fit in Amount. Blocked transactions of other are kept within max blocked
+    /// transactions of this engine, oldest dropped first.
+    pub fn merge(&mut self, other: InMemoryTransactionEngine) -> Result<(), TransactionError> {
+        let other_state = other.state.into_inner().unwrap();
+        let other_clients = other.clients.into_inner().unwrap();
+        let state = self.state.get_mut().unwrap();
+        let clients = self.clients.get_mut().unwrap();
+
+        let mut colliding: Vec<u64> = other_clients.keys().filter(|client_id| clients.contains_key(client_id)).copied().collect();
+        if !colliding.is_empty() {
+            colliding.sort_unstable();
+            warn!("Not merging engines as clients {:?} are held by both", colliding);
+            return Err(TransactionError::ClientCollision(colliding));
+        }
+        let transaction_ids = state.transaction_ids();
+        if let Some(transaction_id) = other_state.transaction_ids().into_iter().find(|transaction_id| transaction_ids.contains(transaction_id)) {
+            warn!(transaction_id; "Not merging engines as transaction {} is held by both", transaction_id);
+            return Err(TransactionError::DuplicateTransaction);
+        }
+        if state.replay_log.is_some() && other_state.replay_log.is_none() {
+            warn!("Not merging engines as other engine does not keep replay log");
+            return Err(TransactionError::MissingReplayLog);
+        }
+        let Some(ledger_total) = state.ledger_total.checked_add(other_state.ledger_total) else {
+            warn!("Not merging engines as total of both would overflow");
+            return Err(TransactionError::Overflow);
+        };
+
+        state.tranasctions.extend(other_state.tranasctions);
+        clients.extend(other_clients);
+        for blocked in other_state.blocked_transactions.into_values() {
+            state.block(blocked, self.config.max_blocked_transactions);
+        }
+        state.finalized_transactions.extend(other_state.finalized_transactions);
+        state.charged_back.extend(other_state.charged_back);
+        state.resolved_tx_ids.extend(other_state.resolved_tx_ids);
+        state.lock_snapshots.extend(other_state.lock_snapshots);
+        state.ledger_total = ledger_total;
+        state.deferred_transactions.extend(other_state.deferred_transactions);
+        state.type_counts.extend(other_state.type_counts);
+        for (outcome, count) in other_state.rejection_counts {
+            *state.rejection_counts.entry(outcome).or_insert(0) += count;
+        }
+        state.last_timestamps.extend(other_state.last_timestamps);
+        state.held_fees.extend(other_state.held_fees);
+        if let (Some(replay_log), Some(other_replay_log)) = (state.replay_log.as_mut(), other_state.replay_log) {
+            replay_log.extend(other_replay_log);
+        }
+        self.rebuild_client_index();
+        Ok(())
+    }
+
+    /// Takes a fee of rate times held balance out of held of every client with held funds, e.g. 0.001 for 0.1%,
+    /// rounded to four decimal places as per config.rounding. Negative rate pays interest into held instead. Rate beyond -1 to 1 is not taken.
+    /// Meant to be called periodically by caller, it is not part of transaction flow and is not recorded in replay log.
+    ///
+    /// Disputed and pending amounts later leaving held are less by fee, see Client::charge_fee.
+    /// Fees of client add up in accrued_fees.
+    pub fn accrue_held_fees(&self, rate: f64) {
+        if !rate.is_finite() || rate.abs() > 1.0 {
+            warn!("Not accruing held fees at rate {}", rate);
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
         let mut clients = self.clients.lock().unwrap();
+        for client in clients.values_mut().filter(|client| client.held().is_positive()) {
+            let units = client.held().units() as f64 * rate;
+            let units = match self.config.rounding {
+                RoundingMode::HalfUp => units.round(),
+                RoundingMode::HalfEven => units.round_ties_even(),
+                RoundingMode::Truncate => units.trunc(),
+            };
+            let fee = Amount::from_units(units as i64);
+            if fee.is_zero() {
+                continue;
+            }
+            let Some(ledger_total) = state.ledger_total.checked_sub(fee) else {
+                warn!(client_id = client.id(); "Not charging fee {} as total of engine would overflow", fee);
+                continue;
+            };
+            if client.charge_fee(fee).is_err() {
+                continue;
+            }
+            state.ledger_total = ledger_total;
+            *state.held_fees.entry(client.id()).or_insert(Amount::ZERO) += fee;
+        }
+    }
+
+    /// Fees charged to client by accrue_held_fees so far, less interest paid.
+    pub fn accrued_fees(&self, client_id: u64) -> Amount {
+        self.state.lock().unwrap().held_fees.get(&client_id).copied().unwrap_or(Amount::ZERO)
+    }
+
+    /// Drops transaction id from ids kept for client, once transaction is no longer with engine.
+    fn unindex_transaction(client_transactions: &mut HashMap<u64, HashSet<u32>>, client_id: u64, transaction_id: u32) {
+        if let Some(transaction_ids) = client_transactions.get_mut(&client_id) {
+            transaction_ids.remove(&transaction_id);
+            if transaction_ids.is_empty() {
+                client_transactions.remove(&client_id);
+            }
+        }
+    }
+
+    /// Rebuilds ids of transactions per client from transactions engine holds.
+    pub(crate) fn rebuild_client_index(&mut self) {
+        let state = self.state.get_mut().unwrap();
+        state.client_transactions.clear();
+        for (transaction_id, transaction) in state.tranasctions.iter() {
+            state.client_transactions.entry(transaction.client_id()).or_default().insert(*transaction_id);
+        }
+        state.client_finalized.clear();
+        for (position, transaction) in state.finalized_transactions.iter().enumerate() {
+            state.client_finalized.entry(transaction.client_id()).or_default().push(position);
+        }
+        state.client_blocked.clear();
+        for (sequence, blocked) in state.blocked_transactions.iter() {
+            state.client_blocked.entry(blocked.client_id()).or_default().push_back(*sequence);
+        }
+    }
+
+    /// New client can only be created while engine is below configured max clients.
+    fn can_add_client(&self, clients: &HashMap<u64, Client>) -> Result<(), TransactionError> {
+        match self.config.max_clients {
+            Some(max_clients) if clients.len() >= max_clients => Err(TransactionError::ClientLimitExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies transaction and then deferred transactions waiting for it, with state and clients held by caller,
+    /// so transactions of other threads are not applied in between.
+    fn add_locked(&self, state: &mut EngineState, clients: &mut HashMap<u64, Client>, transaction_to_add: RawTransaction)
+        -> Result<(), TransactionError> {
+        let referable_id = transaction_to_add.is_non_refering().then(|| transaction_to_add.transaction_id());
+        let added = self.process_transaction(state, clients, transaction_to_add).into_result();
+        if let (Ok(()), Some(transaction_id)) = (&added, referable_id) {
+            self.apply_deferred(state, clients, transaction_id);
+        }
+        added
+    }
+
+    /// Applies transaction, keeps count of applied transactions per client and type and tells observer outcome.
+    /// Chargeback reversal unlocking account replays transactions blocked meanwhile, right after it.
+    fn process_transaction(&self, state: &mut EngineState, clients: &mut HashMap<u64, Client>,
+        transaction_to_add: RawTransaction) -> TransactionOutcome {
+        let client_id = transaction_to_add.client_id();
+        let transaction_type = transaction_to_add.transaction_type();
+        let kept = (state.observer.is_some() || state.replay_log.is_some()).then(|| transaction_to_add.clone());
+        let outcome = self.apply_transaction(state, clients, transaction_to_add);
+        match outcome {
+            TransactionOutcome::Applied => *state.type_counts.entry(client_id).or_default().entry(transaction_type).or_insert(0) += 1,
+            TransactionOutcome::Deferred => {},
+            _ => *state.rejection_counts.entry(outcome).or_insert(0) += 1,
+        }
+        if let (Some(observer), Some(transaction)) = (state.observer.as_mut(), kept.as_ref()) {
+            observer(transaction, outcome);
+        }
+        if let (Some(replay_log), Some(transaction), TransactionOutcome::Applied) = (state.replay_log.as_mut(), kept, outcome) {
+            replay_log.push(transaction);
+        }
+        if transaction_type == TransactionType::ChargebackReversal && outcome == TransactionOutcome::Applied
+            && clients.get(&client_id).is_some_and(|client| !client.is_locked()) {
+            self.replay_blocked(state, clients, client_id);
+        }
+        outcome
+    }
+
+    fn apply_transaction(&self, state: &mut EngineState, clients: &mut HashMap<u64, Client>,
+        transaction_to_add: RawTransaction) -> TransactionOutcome {
+        if (self.config.reject_zero_client_id && transaction_to_add.client_id() == 0)
+            || (self.config.reject_zero_transaction_id && transaction_to_add.transaction_id() == 0) {
+            warn!(client_id = transaction_to_add.client_id(), transaction_id = transaction_to_add.transaction_id(); "Rejecting {:?} as zero id is not taken", &transaction_to_add);
+            return TransactionOutcome::RejectedZeroId;
+        }
 
         if let Some(client) = clients.get(&transaction_to_add.client_id()) {
-            if client.is_locked() {
-                println!("Skipping this transaction as client account is locked {:?}", &transaction_to_add);
-                self.blocked_transactions.push(transaction_to_add);
-                return false;
+            let let_through = match transaction_to_add {
+                RawTransaction::ChargebackReversal { .. } => true,
+                RawTransaction::Deposit { .. } => self.config.allow_deposits_when_locked,
+                _ => false,
+            };
+            if client.is_locked() && !let_through {
+                warn!(client_id = transaction_to_add.client_id(), transaction_id = transaction_to_add.transaction_id(); "Skipping this transaction as client account is locked {:?}", &transaction_to_add);
+                state.block(transaction_to_add, self.config.max_blocked_transactions);
+                return TransactionOutcome::RejectedLocked;
             }
         }
 
         match transaction_to_add {
-            Transaction::Deposit { client_id, transaction_id, amount}
-                | Transaction::Withdrawal { client_id, transaction_id, amount } => {
+            RawTransaction::Deposit { client_id, transaction_id, amount}
+                | RawTransaction::Withdrawal { client_id, transaction_id, amount }
+                | RawTransaction::PendingDeposit { client_id, transaction_id, amount } => {
+                if self.config.reject_duplicate_tx && state.tranasctions.contains_key(&transaction_id) {
+                    warn!(client_id, transaction_id; "Skipping {:?} as transaction {} already exists", &transaction_to_add, transaction_id);
+                    return TransactionOutcome::RejectedDuplicate;
+                }
+                let ledger_total = match transaction_to_add {
+                    RawTransaction::Withdrawal { .. } => state.ledger_total.checked_sub(amount),
+                    _ => state.ledger_total.checked_add(amount),
+                };
+                let Some(ledger_total) = ledger_total else {
+                    warn!(client_id, transaction_id; "Rejecting {:?} as total of engine would overflow", &transaction_to_add);
+                    return TransactionOutcome::RejectedOverflow;
+                };
                 let added = match clients.get_mut(&client_id) {
-                    Some(existing_client) => { existing_client.apply_transaction(&transaction_to_add, amount) },
+                    // only deposit gets this far for locked account, see allow_deposits_when_locked.
+                    Some(existing_client) if existing_client.is_locked() => existing_client.credit(amount),
+                    Some(existing_client) => existing_client.apply_transaction(&transaction_to_add, amount),
+                    // client comes into being with a deposit, there is nothing to withdraw before that.
+                    None if matches!(transaction_to_add, RawTransaction::Withdrawal { .. }) => {
+                        warn!(client_id, transaction_id; "Rejecting {:?} as client has no account yet", &transaction_to_add);
+                        return TransactionOutcome::RejectedInsufficientFunds;
+                    },
                     None => {
+                        if let Err(err) = self.can_add_client(clients) {
+                            warn!(client_id, transaction_id; "Skipping {:?} as {}", &transaction_to_add, err);
+                            return TransactionOutcome::RejectedClientLimit;
+                        }
+                        // client is kept only once a transaction of it is applied, so that reports do not carry
+                        // clients which never had one.
                         let mut client = Client::new(client_id);
                         let added = client.apply_transaction(&transaction_to_add, amount);
-                        clients.insert(client_id, client);
+                        if added.is_ok() {
+                            clients.insert(client_id, client);
+                        }
                         added
                     },
                 };
-                if added {
-                    transactions.insert(transaction_id, transaction_to_add);
-                    true
-                } else {
-                    false
+                if let Err(err) = added {
+                    return TransactionOutcome::refused(err);
+                }
+                state.ledger_total = ledger_total;
+                let stored = match transaction_to_add {
+                    RawTransaction::Withdrawal { .. } => Transaction::Withdrawal { client_id, transaction_id, amount },
+                    RawTransaction::PendingDeposit { .. } => Transaction::PendingDeposit { client_id, transaction_id, amount },
+                    _ => Transaction::Deposit { client_id, transaction_id, amount },
+                };
+                // with reject_duplicate_tx false, transaction of other client may be replaced.
+                if let Some(replaced) = state.tranasctions.insert(transaction_id, stored) {
+                    Self::unindex_transaction(&mut state.client_transactions, replaced.client_id(), transaction_id);
                 }
+                state.client_transactions.entry(client_id).or_default().insert(transaction_id);
+                TransactionOutcome::Applied
             }
-            Transaction::Dispute { client_id, transaction_id } => {
+            RawTransaction::Dispute { client_id, transaction_id, amount: portion } => {
                 if let Some(client) = clients.get_mut(&client_id) {
-                    return match transactions.remove(&transaction_id) {
+                    return match state.tranasctions.remove(&transaction_id) {
+                        Some(existing_transaction) if existing_transaction.client_id() != client_id => {
+                            warn!(client_id, transaction_id; "Rejecting {:?} as transaction {} belongs to other client", &transaction_to_add, transaction_id);
+                            state.tranasctions.insert(transaction_id, existing_transaction);
+                            TransactionOutcome::RejectedWrongClient
+                        },
+                        Some(Transaction::Deposit { client_id: owner, transaction_id, amount })
+                            if self.config.dispute_policy == DisputePolicy::RejectInsufficientAvailable
+                                && client.available() < portion.unwrap_or(amount) => {
+                            warn!(client_id, transaction_id; "Rejecting dispute of {} as available funds are less than {}", transaction_id, portion.unwrap_or(amount));
+                            state.tranasctions.insert(transaction_id, Transaction::Deposit { client_id: owner, transaction_id, amount });
+                            TransactionOutcome::RejectedInsufficientFunds
+                        },
                         Some(existing_transaction) => {
-                            match existing_transaction.make_disputed_transaction() {
+                            let undisputed = existing_transaction.clone();
+                            match existing_transaction.make_disputed_transaction(portion) {
                                 Ok((disputed_transaction, amount)) => {
-                                    client.apply_transaction(&transaction_to_add, amount);
-                                    transactions.insert(transaction_id, disputed_transaction);
+                                    // claim on disputed withdrawal is held with client, while ledger has it withdrawn.
+                                    let ledger_total = match disputed_transaction {
+                                        Transaction::DisputedWithdrawal { .. } => state.ledger_total.checked_add(amount),
+                                        _ => Some(state.ledger_total),
+                                    };
+                                    let disputed = ledger_total.ok_or(TransactionError::Overflow).and_then(|ledger_total| {
+                                        match disputed_transaction {
+                                            Transaction::DisputedDeposit { .. }
+                                                if self.config.dispute_policy == DisputePolicy::HoldAvailable => client.hold_available(amount),
+                                            _ => client.apply_dispute_transaction(&transaction_to_add, &disputed_transaction),
+                                        }.map(|()| ledger_total)
+                                    });
+                                    match disputed {
+                                        Ok(ledger_total) => {
+                                            state.ledger_total = ledger_total;
+                                            state.tranasctions.insert(transaction_id, disputed_transaction);
+                                            TransactionOutcome::Applied
+                                        },
+                                        Err(err) => {
+                                            warn!(client_id, transaction_id; "Rejecting {:?} as {}", &transaction_to_add, err);
+                                            state.tranasctions.insert(transaction_id, undisputed);
+                                            TransactionOutcome::refused(err)
+                                        },
+                                    }
                                 },
                                 Err(transaction) => {
                                     // non disputable transaction are put back as we removed earlier.
-                                    // this can happen when a transaction is disputed twice, and we should keep one.
-                                    transactions.insert(transaction_id, transaction);
+                                    // this can happen when a transaction is disputed twice, funds moved only on first one,
+                                    // or when disputed portion is more than transaction amount.
+                                    warn!(client_id, transaction_id; "Neglecting {:?} as {:?} can not be disputed", &transaction_to_add, transaction);
+                                    state.tranasctions.insert(transaction_id, transaction);
+                                    TransactionOutcome::RejectedNotDisputable
                                 },
                             }
-                            true
-                        },
-                        None => {
-                            eprintln!("Skipping {} as not present with engine", transaction_id);
-                            false
                         },
+                        None => Self::defer_or_skip(&mut state.deferred_transactions, &state.resolved_tx_ids, &self.config, transaction_to_add),
                     }
                 }
-                false
+                Self::defer_or_skip(&mut state.deferred_transactions, &state.resolved_tx_ids, &self.config, transaction_to_add)
             },
-            Transaction::Reslove { client_id, transaction_id }
-                | Transaction::Chargeback { client_id, transaction_id } => {
+            RawTransaction::Reslove { client_id, transaction_id }
+                | RawTransaction::Chargeback { client_id, transaction_id } => {
                 if let Some(client) = clients.get_mut(&client_id) {
-                    return match transactions.remove(&transaction_id) {
+                    return match state.tranasctions.remove(&transaction_id) {
+                        Some(existing_transaction) if existing_transaction.client_id() != client_id => {
+                            warn!(client_id, transaction_id; "Rejecting {:?} as transaction {} belongs to other client", &transaction_to_add, transaction_id);
+                            state.tranasctions.insert(transaction_id, existing_transaction);
+                            TransactionOutcome::RejectedWrongClient
+                        },
                         Some(existing_transaction) if existing_transaction.is_disputed() => {
-                            if let Ok((disputed_transaction, amount)) = existing_transaction
-                                .get_disputed_transaction() {
-                                self.finalized_transactions.push(disputed_transaction);
-                                client.apply_transaction(&transaction_to_add, amount);
+                            // transaction as it was before dispute, checked before client is touched.
+                            let (disputed_transaction, amount) = match existing_transaction.clone().get_disputed_transaction() {
+                                Ok(undisputed) => undisputed,
+                                Err(transaction) => {
+                                    error!(client_id, transaction_id; "Rejecting {:?} as {:?} passed dispute check without being disputed", &transaction_to_add, transaction);
+                                    debug_assert!(transaction.is_disputed(), "{:?} is disputed but has no undisputed form", transaction);
+                                    state.tranasctions.insert(transaction_id, transaction);
+                                    return TransactionOutcome::RejectedNotDisputed;
+                                },
+                            };
+                            let before = client.clone();
+                            let settled = client.apply_dispute_transaction(&transaction_to_add, &existing_transaction).and_then(|()| {
+                                // fee taken out of held earlier was taken out of ledger then, see accrue_held_fees.
+                                let released = before.held_fee().checked_sub(client.held_fee())
+                                    .and_then(|held_fee| amount.checked_sub(held_fee));
+                                // chargeback of deposit takes money out, resolve of withdrawal drops claim of client.
+                                // chargeback of withdrawal keeps money with client, which ledger already has since dispute.
+                                match (&transaction_to_add, &disputed_transaction) {
+                                    (RawTransaction::Chargeback { .. }, Transaction::Deposit { .. })
+                                    | (RawTransaction::Reslove { .. }, Transaction::Withdrawal { .. }) =>
+                                        released.and_then(|released| state.ledger_total.checked_sub(released)),
+                                    _ => Some(state.ledger_total),
+                                }.ok_or(TransactionError::Overflow)
+                            });
+                            match settled {
+                                Ok(ledger_total) => state.ledger_total = ledger_total,
+                                Err(err) => {
+                                    warn!(client_id, transaction_id; "Rejecting {:?} as {}", &transaction_to_add, err);
+                                    *client = before;
+                                    state.tranasctions.insert(transaction_id, existing_transaction);
+                                    return TransactionOutcome::refused(err);
+                                },
                             }
-                            true
+                            if let RawTransaction::Chargeback { .. } = transaction_to_add {
+                                state.lock_snapshots.insert(client_id, before);
+                                state.charged_back.insert(transaction_id);
+                            }
+                            state.finalize(disputed_transaction);
+                            // part left out of partial dispute can still be disputed.
+                            match existing_transaction.undisputed_remainder() {
+                                Some(remainder) => { state.tranasctions.insert(transaction_id, remainder); },
+                                None => {
+                                    Self::unindex_transaction(&mut state.client_transactions, client_id, transaction_id);
+                                    state.resolved_tx_ids.insert(transaction_id);
+                                },
+                            }
+                            TransactionOutcome::Applied
                         },
                         Some(existing_transaction) => {
-                            eprintln!("Neglecting {:?} as not disputed transaction", existing_transaction);
-                            transactions.insert(transaction_id, existing_transaction);
-                            false
+                            warn!(client_id, transaction_id; "Neglecting {:?} as not disputed transaction", existing_transaction);
+                            state.tranasctions.insert(transaction_id, existing_transaction);
+                            TransactionOutcome::RejectedNotDisputed
                         }
-                        None => {
-                            eprintln!("Skipping {} as not present with engine", transaction_id);
-                            false
+                        None => Self::defer_or_skip(&mut state.deferred_transactions, &state.resolved_tx_ids, &self.config, transaction_to_add),
+                    }
+                }
+                Self::defer_or_skip(&mut state.deferred_transactions, &state.resolved_tx_ids, &self.config, transaction_to_add)
+            },
+            RawTransaction::Capture { client_id, transaction_id } => {
+                if let Some(client) = clients.get_mut(&client_id) {
+                    return match state.tranasctions.remove(&transaction_id) {
+                        Some(existing_transaction) if existing_transaction.client_id() != client_id => {
+                            warn!(client_id, transaction_id; "Rejecting {:?} as transaction {} belongs to other client", &transaction_to_add, transaction_id);
+                            state.tranasctions.insert(transaction_id, existing_transaction);
+                            TransactionOutcome::RejectedWrongClient
+                        },
+                        Some(existing_transaction) => {
+                            let pending = existing_transaction.clone();
+                            match existing_transaction.make_captured_transaction() {
+                                Ok((captured_transaction, amount)) => match client.apply_transaction(&transaction_to_add, amount) {
+                                    Ok(()) => {
+                                        state.tranasctions.insert(transaction_id, captured_transaction);
+                                        TransactionOutcome::Applied
+                                    },
+                                    Err(err) => {
+                                        warn!(client_id, transaction_id; "Rejecting {:?} as {}", &transaction_to_add, err);
+                                        state.tranasctions.insert(transaction_id, pending);
+                                        TransactionOutcome::refused(err)
+                                    },
+                                },
+                                Err(transaction) => {
+                                    warn!(client_id, transaction_id; "Neglecting {:?} as not pending transaction", transaction);
+                                    state.tranasctions.insert(transaction_id, transaction);
+                                    TransactionOutcome::RejectedNotPending
+                                },
+                            }
                         },
+                        None => Self::defer_or_skip(&mut state.deferred_transactions, &state.resolved_tx_ids, &self.config, transaction_to_add),
                     }
                 }
-                false
+                Self::defer_or_skip(&mut state.deferred_transactions, &state.resolved_tx_ids, &self.config, transaction_to_add)
+            },
+            RawTransaction::ChargebackReversal { client_id, transaction_id } => {
+                let charged_back = state.client_finalized.get(&client_id).into_iter().flatten()
+                    .rev()
+                    .map(|position| &state.finalized_transactions[*position])
+                    .find(|finalized| finalized.transaction_id() == transaction_id)
+                    .filter(|_| state.charged_back.contains(&transaction_id))
+                    .cloned();
+                match (clients.get_mut(&client_id), charged_back) {
+                    (Some(client), Some(charged_back)) if charged_back.client_id() == client_id => {
+                        // money chargeback of deposit took out comes back, withdrawal chargeback gave back goes out again.
+                        let ledger_total = match charged_back {
+                            Transaction::Deposit { amount, .. } => state.ledger_total.checked_add(amount),
+                            Transaction::Withdrawal { amount, .. } => state.ledger_total.checked_sub(amount),
+                            _ => Some(state.ledger_total),
+                        };
+                        let reversed = ledger_total.ok_or(TransactionError::Overflow)
+                            .and_then(|ledger_total| client.reverse_chargeback(&charged_back).map(|()| ledger_total));
+                        match reversed {
+                            Ok(ledger_total) => state.ledger_total = ledger_total,
+                            Err(err) => {
+                                warn!(client_id, transaction_id; "Rejecting {:?} as {}", &transaction_to_add, err);
+                                return TransactionOutcome::refused(err);
+                            },
+                        }
+                        state.charged_back.remove(&transaction_id);
+                        TransactionOutcome::Applied
+                    },
+                    _ => {
+                        warn!(client_id, transaction_id; "Neglecting {:?} as not charged back transaction of client", &transaction_to_add);
+                        TransactionOutcome::RejectedNotChargedBack
+                    },
+                }
             },
-            _ => {
-                eprintln!("This should not come here");
-                false
-            }
         }
     }
 
-    fn snap_shot_clients(&self) -> Vec<Client> {
+    /// Referring transaction whose target is not with engine is either kept aside for later or skipped.
+    /// Target that was already resolved or charged back is not coming back, so such transaction is always skipped.
+    fn defer_or_skip(deferred: &mut Vec<RawTransaction>, resolved_tx_ids: &HashSet<u32>, config: &EngineConfig,
+        transaction: RawTransaction) -> TransactionOutcome {
+        if resolved_tx_ids.contains(&transaction.transaction_id()) {
+            warn!(client_id = transaction.client_id(), transaction_id = transaction.transaction_id(); "Skipping {:?} as referred transaction is already finalized", transaction);
+            TransactionOutcome::RejectedFinalized
+        } else if config.defer_unknown_references {
+            debug!(client_id = transaction.client_id(), transaction_id = transaction.transaction_id(); "Deferring {:?} as referred transaction not present with engine", transaction);
+            deferred.push(transaction);
+            TransactionOutcome::Deferred
+        } else {
+            warn!(client_id = transaction.client_id(), transaction_id = transaction.transaction_id(); "Skipping {:?} as referred transaction not present with engine", transaction);
+            TransactionOutcome::NotFound
+        }
+    }
+
+    /// Applies, in arrival order, deferred transactions that were waiting for transaction_id.
+    fn apply_deferred(&self, state: &mut EngineState, clients: &mut HashMap<u64, Client>, transaction_id: u32) {
+        let (ready, waiting): (Vec<RawTransaction>, Vec<RawTransaction>) = std::mem::take(&mut state.deferred_transactions)
+            .into_iter()
+            .partition(|deferred| deferred.transaction_id() == transaction_id);
+        state.deferred_transactions = waiting;
+        for deferred in ready {
+            self.process_transaction(state, clients, deferred);
+        }
+    }
+
+    /// Compares money that came in and went out through accepted transactions with sum of all
+    /// client totals (available + held). Any difference means client balances were not updated
+    /// correctly and is returned as Err.
+    pub fn conservation_check(&self) -> Result<(), Amount> {
+        let state = self.state.lock().unwrap();
         let clients = self.clients.lock().unwrap();
-        clients.values().map(|client| client.clone()).collect()
+        let clients_total: Amount = clients.values().map(|client| client.total()).sum();
+        let discrepancy = clients_total - state.ledger_total;
+        if !discrepancy.is_zero() {
+            Err(discrepancy)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks what must hold of engine whatever transactions it got: held of every client is not negative,
+    /// total of every client is available plus held, every transaction with engine belongs to a known client,
+    /// and client balances add up to ledger. Returns every violation found.
+    pub fn check_invariants(&self) -> Result<(), Vec<String>> {
+        let mut violations = Vec::new();
+        {
+            let state = self.state.lock().unwrap();
+            let clients = self.clients.lock().unwrap();
+            for client in clients.values() {
+                if client.held() < Amount::ZERO {
+                    violations.push(format!("client {} has negative held {}", client.id(), client.held()));
+                }
+                if client.total() != client.available() + client.held() {
+                    violations.push(format!("client {} total {} is not available {} plus held {}",
+                        client.id(), client.total(), client.available(), client.held()));
+                }
+            }
+            for (transaction_id, transaction) in state.tranasctions.iter() {
+                if !clients.contains_key(&transaction.client_id()) {
+                    violations.push(format!("transaction {} refers to missing client {}", transaction_id, transaction.client_id()));
+                }
+            }
+        }
+        if let Err(discrepancy) = self.conservation_check() {
+            violations.push(format!("client balances differ from ledger by {}", discrepancy));
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// Engine with default config, with every transaction of log applied in order. Replaying replay_log of an engine
+/// with default config, enabled while it was new, reproduces its clients and transactions.
+#[cfg(feature = "std")]
+pub fn replay(log: &[RawTransaction]) -> InMemoryTransactionEngine {
+    let engine = InMemoryTransactionEngine::new();
+    for transaction in log {
+        if let Err(err) = engine.add_transaction(transaction.clone()) {
+            warn!(client_id = transaction.client_id(), transaction_id = transaction.transaction_id(); "Replayed {:?} is not applied, {}", transaction, err);
+        }
+    }
+    engine
+}
 
-    #[test]
-    fn test_undisputed_transaction_for_resolve_chargeback() {
-        let mut engine = InMemoryTransactionEngine::new();
-        let deposite_trans = Transaction::new("deposit, 1, 1, 1.0");
-        assert!(engine.add_transaction(deposite_trans));
+#[cfg(feature = "std")]
+impl Default for InMemoryTransactionEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds InMemoryTransactionEngine with non default policies.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct InMemoryTransactionEngineBuilder {
+    config: EngineConfig,
+}
 
-        let resolve_trans = Transaction::new("resolve, 1, 1, 1.0");
-        assert!(!engine.add_transaction(resolve_trans));
+#[cfg(feature = "std")]
+impl InMemoryTransactionEngineBuilder {
+    pub fn new() -> Self {
+        InMemoryTransactionEngineBuilder { config: EngineConfig::default() }
+    }
 
-        let resolve_trans = Transaction::new("chargeback, 1, 1, 1.0");
-        assert!(!engine.add_transaction(resolve_trans));
+    /// Once engine has max_clients distinct clients, transaction creating new client is rejected.
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.config.max_clients = Some(max_clients);
+        self
+    }
 
-        let disputed_trans = Transaction::new("dispute, 1, 1");
-        let resolve_trans = Transaction::new("resolve, 1, 1");
-        assert!(engine.add_transaction(disputed_trans));
-        assert!(engine.add_transaction(resolve_trans));
+    /// Keeps Dispute, Resolve and Chargeback referring to unknown transaction, till referred transaction arrives.
+    pub fn defer_unknown_references(mut self, defer: bool) -> Self {
+        self.config.defer_unknown_references = defer;
+        self
+    }
 
-        // after above resolve, this transaction should not be active with engine
-        let disputed_trans = Transaction::new("dispute, 1, 1");
-        assert!(!engine.add_transaction(disputed_trans));
+    /// Number of threads parallel processing should use, defaults to available parallelism.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.config.workers = workers;
+        self
     }
 
-    #[test]
-    fn test_charge_back_should_skip_all_future_transaction() {
-        let mut engine = InMemoryTransactionEngine::new();
-        let deposite_trans = Transaction::new("deposit, 1, 1, 1.0");
-        assert!(engine.add_transaction(deposite_trans));
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
 
-        let disputed_trans = Transaction::new("dispute, 1, 1");
-        let resolve_trans = Transaction::new("chargeback, 1, 1");
-        assert!(engine.add_transaction(disputed_trans));
-        assert!(engine.add_transaction(resolve_trans));
+    /// How to handle dispute of deposit whose funds are no longer available.
+    pub fn dispute_policy(mut self, dispute_policy: DisputePolicy) -> Self {
+        self.config.dispute_policy = dispute_policy;
+        self
+    }
 
-        let deposite_trans = Transaction::new("deposit, 1, 2, 1.0");
-        assert!(!engine.add_transaction(deposite_trans));
+    /// Shorthand for dispute_policy, true being AllowNegativeAvailable.
+    pub fn allow_negative_available(self, allow: bool) -> Self {
+        self.dispute_policy(if allow {
+            DisputePolicy::AllowNegativeAvailable
+        } else {
+            DisputePolicy::RejectInsufficientAvailable
+        })
     }
 
-    #[test]
-    fn test_withdrawal_shold_be_skipped_if_low_balance() {
-        let mut engine = InMemoryTransactionEngine::new();
-        let deposite_trans = Transaction::new("deposit, 1, 1, 1.0");
-        assert!(engine.add_transaction(deposite_trans));
+    /// Skip deposit, withdrawal or pending reusing id of transaction already with engine, on by default.
+    pub fn reject_duplicate_tx(mut self, reject: bool) -> Self {
+        self.config.reject_duplicate_tx = reject;
+        self
+    }
+
+    /// Reject transaction timestamped before last applied transaction of its client, instead of applying it in file order.
+    pub fn strict_ordering(mut self, strict: bool) -> Self {
+        self.config.strict_ordering = strict;
+        self
+    }
+
+    /// Transactions kept for locked accounts, to be replayed on unlock or audited. Once there are as many,
+    /// oldest one is dropped for every new one.
+    pub fn max_blocked_transactions(mut self, max_blocked_transactions: usize) -> Self {
+        self.config.max_blocked_transactions = max_blocked_transactions;
+        self
+    }
+
+    /// Reject transactions of client id zero.
+    pub fn reject_zero_client_id(mut self, reject: bool) -> Self {
+        self.config.reject_zero_client_id = reject;
+        self
+    }
+
+    /// Reject transactions with transaction id zero, including dispute, resolve and chargeback referring to it.
+    pub fn reject_zero_transaction_id(mut self, reject: bool) -> Self {
+        self.config.reject_zero_transaction_id = reject;
+        self
+    }
+
+    /// Apply deposits to locked accounts instead of keeping them with blocked transactions.
+    /// Withdrawals, disputes and every other transaction of locked account stay blocked.
+    pub fn allow_deposits_when_locked(mut self, allow: bool) -> Self {
+        self.config.allow_deposits_when_locked = allow;
+        self
+    }
+
+    /// Rounding of amounts engine works out itself, banker's rounding unless set.
+    pub fn with_rounding(mut self, rounding: RoundingMode) -> Self {
+        self.config.rounding = rounding;
+        self
+    }
+
+    pub fn build(self) -> InMemoryTransactionEngine {
+        InMemoryTransactionEngine::with_config(self.config)
+    }
+}
 
-        let withdrawal_trans = Transaction::new("withdrawal, 1, 2, 1.1");
-        assert!(!engine.add_transaction(withdrawal_trans));
+#[cfg(feature = "std")]
+impl TransactionEngine for InMemoryTransactionEngine {
+    /// This method add transaction to Engine.
+    /// Following are rules
+    /// 1. Client Account has to be not in locked state. It will do nothing if account is locked,
+    ///    but for deposits when engine is built with allow_deposits_when_locked.
+    /// 2. Deposit will simply increase available balance.
+    /// 3. Withdraw will check if account has more available balance than withdrawal amount, it will let transaction go.
+    ///    Client with overdraft limit can withdraw till available balance reaches -limit.
+    /// 4. Only Transaction that can be disputed are Deposit or Withdrawal, and only by client owning it.
+    ///    Dispute of Deposit whose funds are no longer available follows configured DisputePolicy.
+    ///    Dispute with amount, e.g. `dispute, 1, 1, 30.0`, disputes only that part of transaction.
+    /// 5. Only Disputed Transaction can be, by client owning it,
+    ///    a. Resolved - once resolved, transaction is removed from tranasctions,
+    ///    otherwise one can keep disputing same transaction and gain system.
+    ///    b. Chargeback - once applied, transaction is removed from tranasctions,
+    ///    also client account is locked and no further transaction is allowed on client.
+    ///    Resolve and Chargeback act on disputed part only, what was left out of dispute stays with tranasctions
+    ///    and can be disputed on its own.
+    /// 6. Deposit or Withdrawal reusing id of a transaction already with engine is skipped, unless
+    ///    engine is built with reject_duplicate_tx false.
+    /// 7. New client is not created once engine reached configured max clients.
+    /// 8. Pending deposit only increases held balance, till Capture by client owning it moves it to available.
+    ///    Only once captured it becomes regular Deposit, that can be disputed.
+    /// 9. With defer_unknown_references, Dispute, Resolve and Chargeback referring to transaction not yet seen
+    ///    are kept aside and applied as soon as referred transaction gets added.
+    /// 10. Chargeback Reversal, even for locked account, undoes chargeback of client's transaction and unlocks account.
+    ///     It can be applied only once per chargeback.
+    /// 11. Client id or transaction id zero, often a sentinel of upstream systems, is rejected when engine is built
+    ///     with reject_zero_client_id or reject_zero_transaction_id. By default zero is a valid id.
+    fn add_transaction(&self, transaction_to_add: RawTransaction) -> Result<(), TransactionError> {
+        let mut state = self.state.lock().unwrap();
+        let mut clients = self.clients.lock().unwrap();
+        self.add_locked(&mut state, &mut clients, transaction_to_add)
+    }
 
-        let disputed_trans = Transaction::new("dispute, 1, 2");
-        assert!(!engine.add_transaction(disputed_trans));
+    /// Transaction timestamped before last applied transaction of its client is out of order. It is rejected
+    /// with strict_ordering, and applied in file order otherwise. Transaction without timestamp is always
+    /// taken in file order.
+    fn add_transaction_at(&self, transaction: RawTransaction, timestamp: Option<DateTime<Utc>>) -> Result<(), TransactionError> {
+        let client_id = transaction.client_id();
+        let transaction_id = transaction.transaction_id();
+        let timestamp = match timestamp {
+            Some(timestamp) => timestamp,
+            None => return self.add_transaction(transaction),
+        };
+        // held from check through to recording timestamp, so transactions of other threads do not land in between.
+        let mut state = self.state.lock().unwrap();
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(last) = state.last_timestamps.get(&client_id).copied().filter(|last| timestamp < *last) {
+            if self.config.strict_ordering {
+                warn!(client_id, transaction_id; "Rejecting {:?} as {} is before {}", &transaction, timestamp, last);
+                *state.rejection_counts.entry(TransactionOutcome::RejectedOutOfOrder).or_insert(0) += 1;
+                if let Some(observer) = state.observer.as_mut() {
+                    observer(&transaction, TransactionOutcome::RejectedOutOfOrder);
+                }
+                return Err(TransactionError::OutOfOrder);
+            }
+            warn!(client_id, transaction_id; "Applying {:?} in file order though {} is before {}", &transaction, timestamp, last);
+        }
+        let added = self.add_locked(&mut state, &mut clients, transaction);
+        if added.is_ok() {
+            let last = state.last_timestamps.entry(client_id).or_insert(timestamp);
+            *last = (*last).max(timestamp);
+        }
+        added
+    }
 
-        let disputed_trans = Transaction::new("dispute, 1, 1");
-        assert!(engine.add_transaction(disputed_trans));
+    /// Whole engine is copied before batch, so this suits small batches on modest engines.
+    /// Deferred transaction counts as not applied, failing batch. Observer has already been told about
+    /// transactions applied before failing one, it is not told again about their rollback.
+    fn add_transactions_atomic(&mut self, batch: Vec<RawTransaction>) -> Result<(), (usize, TransactionError)> {
+        let before = self.take_snapshot();
+        let logged = self.state.get_mut().unwrap().replay_log.as_ref().map(Vec::len);
+        for (index, transaction) in batch.into_iter().enumerate() {
+            if let Err(err) = self.add_transaction(transaction) {
+                warn!(index; "Rolling back batch as its transaction is not applied, {}", err);
+                self.restore_snapshot(before);
+                if let (Some(replay_log), Some(logged)) = (self.state.get_mut().unwrap().replay_log.as_mut(), logged) {
+                    replay_log.truncate(logged);
+                }
+                return Err((index, err));
+            }
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+
+    fn snap_shot_clients(&self) -> Vec<Client> {
+        let clients = self.clients.lock().unwrap();
+        clients.values().cloned().collect()
+    }
+
+    fn get_client(&self, client_id: u64) -> Option<Client> {
+        let clients = self.clients.lock().unwrap();
+        clients.get(&client_id).cloned()
+    }
+
+    /// Clients are visited in ascending client id order. Only ids are sorted, clients are not cloned.
+    fn for_each_client(&self, f: &mut dyn FnMut(&Client)) {
+        let clients = self.clients.lock().unwrap();
+        let mut client_ids: Vec<&u64> = clients.keys().collect();
+        client_ids.sort_unstable();
+        client_ids.into_iter().for_each(|client_id| f(&clients[client_id]));
+    }
+
+    /// Rounding of config, so amounts of input round same as amounts engine works out itself.
+    fn rounding(&self) -> Option<RoundingMode> {
+        Some(self.config.rounding)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_undisputed_transaction_for_resolve_chargeback() {
+        let engine = InMemoryTransactionEngine::new();
+        let deposite_trans = RawTransaction::new("deposit, 1, 1, 1.0");
+        assert_eq!(Ok(()), engine.add_transaction(deposite_trans));
+
+        let resolve_trans = RawTransaction::new("resolve, 1, 1, 1.0");
+        assert_eq!(Err(TransactionError::NotDisputed), engine.add_transaction(resolve_trans));
+
+        let resolve_trans = RawTransaction::new("chargeback, 1, 1, 1.0");
+        assert_eq!(Err(TransactionError::NotDisputed), engine.add_transaction(resolve_trans));
+
+        let disputed_trans = RawTransaction::new("dispute, 1, 1");
+        let resolve_trans = RawTransaction::new("resolve, 1, 1");
+        assert_eq!(Ok(()), engine.add_transaction(disputed_trans));
+        assert_eq!(Ok(()), engine.add_transaction(resolve_trans));
+
+        // after above resolve, this transaction should not be active with engine
+        let disputed_trans = RawTransaction::new("dispute, 1, 1");
+        assert_eq!(Err(TransactionError::AlreadyFinalized), engine.add_transaction(disputed_trans));
+    }
+
+    #[test]
+    fn test_charge_back_should_skip_all_future_transaction() {
+        let engine = InMemoryTransactionEngine::new();
+        let deposite_trans = RawTransaction::new("deposit, 1, 1, 1.0");
+        assert_eq!(Ok(()), engine.add_transaction(deposite_trans));
+
+        let disputed_trans = RawTransaction::new("dispute, 1, 1");
+        let resolve_trans = RawTransaction::new("chargeback, 1, 1");
+        assert_eq!(Ok(()), engine.add_transaction(disputed_trans));
+        assert_eq!(Ok(()), engine.add_transaction(resolve_trans));
+
+        let deposite_trans = RawTransaction::new("deposit, 1, 2, 1.0");
+        assert_eq!(Err(TransactionError::AccountLocked), engine.add_transaction(deposite_trans));
+    }
+
+    #[test]
+    fn test_withdrawal_shold_be_skipped_if_low_balance() {
+        let engine = InMemoryTransactionEngine::new();
+        let deposite_trans = RawTransaction::new("deposit, 1, 1, 1.0");
+        assert_eq!(Ok(()), engine.add_transaction(deposite_trans));
+
+        let withdrawal_trans = RawTransaction::new("withdrawal, 1, 2, 1.1");
+        assert_eq!(Err(TransactionError::InsufficientFunds), engine.add_transaction(withdrawal_trans));
+
+        let disputed_trans = RawTransaction::new("dispute, 1, 2");
+        assert_eq!(Err(TransactionError::TransactionNotFound), engine.add_transaction(disputed_trans));
+
+        let disputed_trans = RawTransaction::new("dispute, 1, 1");
+        assert_eq!(Ok(()), engine.add_transaction(disputed_trans));
+    }
+
+    #[test]
+    fn test_new_client_rejected_once_client_cap_reached() {
+        let engine = InMemoryTransactionEngineBuilder::new().max_clients(2).build();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 1.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 2, 2, 1.0")));
+
+        assert_eq!(Err(TransactionError::ClientLimitExceeded), engine.add_transaction(RawTransaction::new("deposit, 3, 3, 1.0")));
+        assert_eq!(2, engine.snap_shot_clients().len());
+
+        // existing clients keep working.
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 4, 1.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("withdrawal, 2, 5, 1.0")));
+    }
+
+    #[test]
+    fn test_conservation_check() {
+        let mut engine = InMemoryTransactionEngine::new();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 10.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 2, 2, 0.1")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 2, 3, 0.2")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("withdrawal, 1, 4, 2.5")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 2, 2")));
+        assert_eq!(Ok(()), engine.conservation_check());
+
+        // deliberately unbalance ledger as if 1.0 deposit never reached client.
+        engine.state.get_mut().unwrap().ledger_total += "1.0".parse().unwrap();
+        assert_eq!(Err("-1.0".parse().unwrap()), engine.conservation_check());
+    }
+
+    #[test]
+    fn test_deferred_dispute_applied_once_deposit_arrives() {
+        let engine = InMemoryTransactionEngineBuilder::new().defer_unknown_references(true).build();
+        assert_eq!(Err(TransactionError::Deferred), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+        assert!(engine.snap_shot_clients().is_empty());
+
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 10.0")));
+        assert_eq!("1, 0, 10, 10, false", engine.snap_shot_clients()[0].to_string());
+        assert!(engine.state.lock().unwrap().deferred_transactions.is_empty());
+    }
+
+    #[test]
+    fn test_pending_deposit_available_only_after_capture() {
+        let engine = InMemoryTransactionEngine::new();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("pending, 1, 1, 10.0")));
+        assert_eq!("1, 0, 10, 10, false", engine.snap_shot_clients()[0].to_string());
+
+        // held funds can not be withdrawn before capture.
+        assert_eq!(Err(TransactionError::InsufficientFunds), engine.add_transaction(RawTransaction::new("withdrawal, 1, 2, 5.0")));
+
+        // only client owning pending deposit can capture it.
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 2, 3, 1.0")));
+        assert_eq!(Err(TransactionError::WrongClient), engine.add_transaction(RawTransaction::new("capture, 2, 1")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("capture, 1, 1")));
+        assert_eq!("1, 10, 0, 10, false", engine.get_client_row(1));
+
+        // captured deposit can not be captured again.
+        assert_eq!(Err(TransactionError::NotPending), engine.add_transaction(RawTransaction::new("capture, 1, 1")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("withdrawal, 1, 2, 5.0")));
+        assert_eq!(Ok(()), engine.conservation_check());
+    }
+
+    #[test]
+    fn test_config_matches_builder() {
+        let engine = InMemoryTransactionEngineBuilder::new()
+            .max_clients(5)
+            .defer_unknown_references(true)
+            .with_workers(3)
+            .allow_negative_available(false)
+            .reject_duplicate_tx(false)
+            .strict_ordering(true)
+            .max_blocked_transactions(7)
+            .reject_zero_client_id(true)
+            .reject_zero_transaction_id(true)
+            .allow_deposits_when_locked(true)
+            .with_rounding(RoundingMode::Truncate)
+            .build();
+        assert_eq!(&EngineConfig {
+            max_clients: Some(5),
+            defer_unknown_references: true,
+            workers: 3,
+            dispute_policy: DisputePolicy::RejectInsufficientAvailable,
+            reject_duplicate_tx: false,
+            strict_ordering: true,
+            max_blocked_transactions: 7,
+            reject_zero_client_id: true,
+            reject_zero_transaction_id: true,
+            allow_deposits_when_locked: true,
+            rounding: RoundingMode::Truncate,
+        }, engine.config());
+        assert_eq!(&EngineConfig::default(), InMemoryTransactionEngine::new().config());
+    }
+
+    #[test]
+    fn test_process_all_returns_outcome_per_transaction() {
+        let engine = InMemoryTransactionEngine::new();
+        let outcomes = process_all(&engine, vec![
+            RawTransaction::new("deposit, 1, 1, 1.0"),
+            RawTransaction::new("withdrawal, 1, 2, 5.0"),
+            RawTransaction::new("dispute, 1, 1"),
+        ]);
+        assert_eq!(vec![Outcome::Applied, Outcome::Rejected, Outcome::Applied], outcomes);
+    }
+
+    #[test]
+    fn test_blocked_and_finalized_csv() {
+        let engine = InMemoryTransactionEngine::new();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 2.5")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("chargeback, 1, 1")));
+        assert_eq!(Err(TransactionError::AccountLocked), engine.add_transaction(RawTransaction::new("deposit, 1, 2, 4.0")));
+
+        let mut finalized = Vec::new();
+        engine.write_finalized_csv(&mut finalized).unwrap();
+        assert_eq!("type,client,tx,amount\ndeposit,1,1,2.5\n", String::from_utf8(finalized).unwrap());
+
+        let mut blocked = Vec::new();
+        engine.write_blocked_csv(&mut blocked).unwrap();
+        assert_eq!("type,client,tx,amount\ndeposit,1,2,4\n", String::from_utf8(blocked).unwrap());
+    }
+
+    #[test]
+    fn test_dispute_by_amount() {
+        let engine = InMemoryTransactionEngine::new();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 5.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 2, 3.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 3, 3.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 2, 4, 7.0")));
+
+        assert_eq!(Ok(1), engine.dispute_by_amount(1, "5.0".parse().unwrap()));
+        assert_eq!("1, 6, 5, 11, false", engine.get_client_row(1));
+        // already disputed transaction is no longer a candidate.
+        assert_eq!(Err(TransactionError::NoMatchingTransaction), engine.dispute_by_amount(1, "5.0".parse().unwrap()));
+        assert_eq!(Err(TransactionError::AmbiguousMatch(vec![2, 3])), engine.dispute_by_amount(1, "3.0".parse().unwrap()));
+        // amount of other client does not match.
+        assert_eq!(Err(TransactionError::NoMatchingTransaction), engine.dispute_by_amount(1, "7.0".parse().unwrap()));
+    }
+
+    impl InMemoryTransactionEngine {
+        fn get_client_row(&self, client_id: u64) -> String {
+            self.get_client(client_id).unwrap().to_string()
+        }
+
+        /// Sorted transaction ids indexed for client, after checking index matches transactions held.
+        fn indexed_ids(&self, client_id: u64) -> Vec<u32> {
+            let state = self.state.lock().unwrap();
+            let mut expected: HashMap<u64, HashSet<u32>> = HashMap::new();
+            for (transaction_id, transaction) in state.tranasctions.iter() {
+                expected.entry(transaction.client_id()).or_default().insert(*transaction_id);
+            }
+            assert_eq!(expected, state.client_transactions);
+            let mut ids: Vec<u32> = state.client_transactions.get(&client_id).into_iter().flatten().copied().collect();
+            ids.sort_unstable();
+            ids
+        }
+    }
+
+    #[test]
+    fn test_client_index_follows_transactions() {
+        let engine = InMemoryTransactionEngineBuilder::new().reject_duplicate_tx(false).build();
+        for input in ["deposit, 1, 1, 5.0", "deposit, 1, 2, 5.0", "withdrawal, 1, 3, 1.0", "deposit, 2, 4, 1.0"] {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+        assert_eq!(Err(TransactionError::InsufficientFunds), engine.add_transaction(RawTransaction::new("withdrawal, 1, 5, 100.0")));
+        assert_eq!(vec![1, 2, 3], engine.indexed_ids(1));
+
+        // disputed transaction stays with engine till resolved or charged back.
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+        assert_eq!(Err(TransactionError::WrongClient), engine.add_transaction(RawTransaction::new("dispute, 2, 2")));
+        assert_eq!(vec![1, 2, 3], engine.indexed_ids(1));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("resolve, 1, 1")));
+        assert_eq!(vec![2, 3], engine.indexed_ids(1));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 3")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("chargeback, 1, 3")));
+        assert_eq!(vec![2], engine.indexed_ids(1));
+
+        // reused id moves to client of latest transaction.
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 2, 2, 1.0")));
+        assert_eq!(Vec::<u32>::new(), engine.indexed_ids(1));
+        assert_eq!(vec![2, 4], engine.indexed_ids(2));
+        assert_eq!(Err(TransactionError::AmbiguousMatch(vec![2, 4])), engine.dispute_by_amount(2, Amount::from_units(10_000)));
+    }
+
+    #[test]
+    fn test_lock_snapshot() {
+        let engine = InMemoryTransactionEngine::new();
+        for input in ["deposit, 1, 1, 5.0", "deposit, 1, 2, 1.5", "withdrawal, 1, 3, 0.5", "dispute, 1, 1"] {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+        assert_eq!(None, engine.lock_snapshot(1));
+
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("chargeback, 1, 1")));
+        let snapshot = engine.lock_snapshot(1).unwrap();
+        assert_eq!("1, 1, 5, 6, false", snapshot.to_string());
+        assert_eq!("1, 1, 0, 1, true", engine.get_client_row(1));
+        assert_eq!(None, engine.lock_snapshot(2));
+    }
+
+    #[test]
+    fn test_client_type_counts() {
+        let engine = InMemoryTransactionEngine::new();
+        for input in ["deposit, 1, 1, 5.0", "deposit, 1, 2, 5.0", "withdrawal, 1, 3, 1.0", "withdrawal, 1, 4, 100.0",
+            "dispute, 1, 1", "resolve, 1, 1", "dispute, 1, 2", "chargeback, 1, 2", "deposit, 1, 5, 1.0", "deposit, 2, 6, 1.0"] {
+            let _ = engine.add_transaction(RawTransaction::new(input));
+        }
+
+        let counts = engine.client_type_counts(1);
+        assert_eq!(HashMap::from([
+            (TransactionType::Deposite, 2),
+            (TransactionType::Withdrawal, 1),
+            (TransactionType::Dispute, 2),
+            (TransactionType::Reslove, 1),
+            (TransactionType::Chargeback, 1),
+        ]), counts);
+        assert!(engine.client_type_counts(3).is_empty());
+    }
+
+    #[test]
+    fn test_amounts_do_not_drift() {
+        let engine = InMemoryTransactionEngine::new();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 0.1")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 2, 0.2")));
+        assert_eq!("1, 0.3, 0, 0.3, false", engine.get_client_row(1));
+    }
+
+    #[test]
+    fn test_duplicate_transaction_id_is_rejected() {
+        let engine = InMemoryTransactionEngine::new();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 1.0")));
+        assert_eq!(Err(TransactionError::DuplicateTransaction), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 5.0")));
+        assert_eq!(Err(TransactionError::DuplicateTransaction), engine.add_transaction(RawTransaction::new("withdrawal, 1, 1, 0.5")));
+        assert_eq!("1, 1, 0, 1, false", engine.get_client_row(1));
+    }
+
+    #[test]
+    fn test_resolve_releases_held_funds() {
+        let engine = InMemoryTransactionEngine::new();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 10.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+        assert_eq!("1, 0, 10, 10, false", engine.get_client_row(1));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("resolve, 1, 1")));
+        assert_eq!("1, 10, 0, 10, false", engine.get_client_row(1));
+        assert_eq!(Ok(()), engine.conservation_check());
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_lifecycle() {
+        let engine = InMemoryTransactionEngine::new();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 10.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("withdrawal, 1, 2, 4.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 2")));
+        assert_eq!("1, 6, 4, 10, false", engine.get_client_row(1));
+        assert_eq!(Ok(()), engine.conservation_check());
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("resolve, 1, 2")));
+        assert_eq!("1, 6, 0, 6, false", engine.get_client_row(1));
+        assert_eq!(Ok(()), engine.conservation_check());
+
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("withdrawal, 1, 3, 1.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 3")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("chargeback, 1, 3")));
+        assert_eq!("1, 6, 0, 6, true", engine.get_client_row(1));
+        assert_eq!(Ok(()), engine.conservation_check());
+    }
+
+    #[test]
+    fn test_deposit_chargeback_takes_out_held_funds() {
+        let engine = InMemoryTransactionEngine::new();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 10.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 2, 3.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("chargeback, 1, 1")));
+        assert_eq!("1, 3, 0, 3, true", engine.get_client_row(1));
+        assert_eq!(Ok(()), engine.conservation_check());
+    }
+
+    #[test]
+    fn test_dispute_policy_for_spent_deposit() {
+        let inputs = ["deposit, 1, 1, 10.0", "withdrawal, 1, 2, 8.0"];
+
+        let engine = InMemoryTransactionEngine::new();
+        for input in inputs {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+        assert_eq!("1, -8, 10, 2, false", engine.get_client_row(1));
+
+        let engine = InMemoryTransactionEngineBuilder::new()
+            .dispute_policy(DisputePolicy::RejectInsufficientAvailable)
+            .build();
+        for input in inputs {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+        assert_eq!(Err(TransactionError::InsufficientFunds), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+        assert_eq!("1, 2, 0, 2, false", engine.get_client_row(1));
+        // deposit stays disputable once funds are back.
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 3, 8.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+        assert_eq!("1, 0, 10, 10, false", engine.get_client_row(1));
+    }
+
+    #[test]
+    fn test_get_client() {
+        let engine = InMemoryTransactionEngine::new();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 1.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 2, 2, 2.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 3, 3, 3.0")));
+
+        let client = engine.get_client(2).unwrap();
+        assert_eq!(2, client.id());
+        assert_eq!("2, 2, 0, 2, false", client.to_string());
+        assert!(engine.get_client(4).is_none());
+    }
+
+    #[test]
+    fn test_stats() {
+        let engine = InMemoryTransactionEngine::new();
+        for input in ["deposit, 1, 1, 1.0", "deposit, 2, 2, 2.0", "deposit, 2, 3, 2.0", "dispute, 2, 2",
+            "chargeback, 2, 2", "deposit, 2, 4, 1.0", "withdrawal, 2, 5, 1.0"] {
+            let _ = engine.add_transaction(RawTransaction::new(input));
+        }
+        assert_eq!(EngineStats {
+            active_transactions: 2,
+            blocked_transactions: 2,
+            finalized_transactions: 1,
+            total_clients: 2,
+            locked_clients: 1,
+        }, engine.stats());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut engine = InMemoryTransactionEngine::new();
+        for input in ["deposit, 1, 1, 1.0", "deposit, 2, 2, 2.0", "dispute, 2, 2", "chargeback, 2, 2"] {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+        assert_eq!(Err(TransactionError::AccountLocked), engine.add_transaction(RawTransaction::new("deposit, 2, 3, 1.0")));
+        engine.reset();
+        assert!(engine.snap_shot_clients().is_empty());
+        assert_eq!(EngineStats::default(), engine.stats());
+        assert_eq!(Ok(()), engine.conservation_check());
+
+        // ids used before reset can be used again.
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 2, 2, 1.0")));
+        assert_eq!("2, 1, 0, 1, false", engine.get_client_row(2));
+    }
+
+    #[test]
+    fn test_blocked_transactions_are_bounded() {
+        let engine = InMemoryTransactionEngineBuilder::new().max_blocked_transactions(3).build();
+        for input in ["deposit, 1, 1, 1.0", "dispute, 1, 1", "chargeback, 1, 1"] {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+        for transaction_id in 2..100 {
+            let deposit = RawTransaction::new(&format!("deposit, 1, {}, 1.0", transaction_id));
+            assert_eq!(Err(TransactionError::AccountLocked), engine.add_transaction(deposit));
+        }
+        assert_eq!(3, engine.stats().blocked_transactions);
+
+        // latest ones are kept.
+        let mut output = Vec::new();
+        engine.write_blocked_csv(&mut output).unwrap();
+        assert_eq!("type,client,tx,amount\ndeposit,1,97,1\ndeposit,1,98,1\ndeposit,1,99,1\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn test_unlock_replays_blocked_transactions() {
+        let engine = InMemoryTransactionEngine::new();
+        for input in ["deposit, 1, 1, 5.0", "deposit, 1, 2, 1.0", "dispute, 1, 1", "chargeback, 1, 1", "deposit, 2, 3, 1.0"] {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+        assert_eq!(Err(TransactionError::AccountLocked), engine.add_transaction(RawTransaction::new("deposit, 1, 4, 2.0")));
+        assert_eq!(Err(TransactionError::AccountLocked), engine.add_transaction(RawTransaction::new("withdrawal, 1, 5, 10.0")));
+        assert_eq!("1, 1, 0, 1, true", engine.get_client_row(1));
+
+        let failed = engine.unlock_client(1);
+        assert_eq!(1, failed.len());
+        assert_eq!(5, failed[0].transaction_id());
+        assert_eq!("1, 3, 0, 3, false", engine.get_client_row(1));
+        assert_eq!(0, engine.stats().blocked_transactions);
+    }
+
+    #[test]
+    fn test_cross_client_dispute_rejected() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 5.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 2, 2, 1.0")).unwrap();
+
+        assert_eq!(Err(TransactionError::WrongClient), engine.add_transaction(RawTransaction::new("dispute, 2, 1")));
+        assert_eq!("1, 5, 0, 5, false", engine.get_client_row(1));
+        assert_eq!("2, 1, 0, 1, false", engine.get_client_row(2));
+
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+        assert_eq!(Err(TransactionError::WrongClient), engine.add_transaction(RawTransaction::new("chargeback, 2, 1")));
+        assert_eq!(Err(TransactionError::WrongClient), engine.add_transaction(RawTransaction::new("resolve, 2, 1")));
+        assert_eq!("1, 0, 5, 5, false", engine.get_client_row(1));
+        assert_eq!("2, 1, 0, 1, false", engine.get_client_row(2));
+    }
+
+    #[test]
+    fn test_duplicate_tx_applied_when_not_rejected() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 1.0")).unwrap();
+        assert_eq!(Err(TransactionError::DuplicateTransaction), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 2.0")));
+        assert_eq!("1, 1, 0, 1, false", engine.get_client_row(1));
+
+        let engine = InMemoryTransactionEngineBuilder::new().reject_duplicate_tx(false).build();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 1.0")).unwrap();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 2.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+        assert_eq!("1, 1, 2, 3, false", engine.get_client_row(1));
+    }
+
+    #[test]
+    fn test_audit_log_has_only_finalized_of_client() {
+        let engine = InMemoryTransactionEngine::new();
+        for input in ["deposit, 1, 1, 1.0", "deposit, 2, 2, 2.0", "deposit, 1, 3, 3.0", "deposit, 1, 4, 4.0",
+            "dispute, 1, 3", "resolve, 1, 3", "dispute, 2, 2", "chargeback, 2, 2", "dispute, 1, 1", "chargeback, 1, 1",
+            "dispute, 1, 4"] {
+            let _ = engine.add_transaction(RawTransaction::new(input));
+        }
+
+        let rows: Vec<String> = engine.audit_log(1).iter().map(|transaction| transaction.to_string()).collect();
+        assert_eq!(vec!["deposit,1,3,3", "deposit,1,1,1"], rows);
+        assert_eq!(1, engine.audit_log(2).len());
+        assert!(engine.audit_log(3).is_empty());
+    }
+
+    #[test]
+    fn test_blocked_index_follows_eviction_and_unlock() {
+        let engine = InMemoryTransactionEngineBuilder::new().max_blocked_transactions(3).build();
+        for input in ["deposit, 1, 1, 5.0", "deposit, 2, 2, 5.0", "dispute, 1, 1", "chargeback, 1, 1",
+            "dispute, 2, 2", "chargeback, 2, 2"] {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+        for input in ["deposit, 1, 3, 1.0", "deposit, 2, 4, 1.0", "deposit, 1, 5, 1.0", "deposit, 2, 6, 1.0"] {
+            assert_eq!(Err(TransactionError::AccountLocked), engine.add_transaction(RawTransaction::new(input)));
+        }
+        // transaction 3 is dropped, being oldest of all.
+        let ids = |transactions: Vec<RawTransaction>| transactions.iter().map(RawTransaction::transaction_id).collect::<Vec<u32>>();
+        assert_eq!(vec![5], ids(engine.blocked_transactions_for(1)));
+        assert_eq!(vec![4, 6], ids(engine.blocked_transactions_for(2)));
+
+        assert!(engine.unlock_client(2).is_empty());
+        assert_eq!(vec![5], ids(engine.blocked_transactions()));
+        assert!(engine.blocked_transactions_for(2).is_empty());
+        assert_eq!("2, 2, 0, 2, false", engine.get_client_row(2));
+
+        let mut restored = InMemoryTransactionEngine::new();
+        restored.restore_snapshot(engine.take_snapshot());
+        assert_eq!(vec![5], ids(restored.blocked_transactions_for(1)));
+        assert!(restored.unlock_client(1).is_empty());
+        assert!(restored.blocked_transactions().is_empty());
+        assert_eq!(vec![1], restored.audit_log(1).iter().map(Transaction::transaction_id).collect::<Vec<u32>>());
+        assert_eq!(vec![2], restored.audit_log(2).iter().map(Transaction::transaction_id).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_double_dispute_moves_funds_once() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 10.0")).unwrap();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+        assert_eq!(Err(TransactionError::NotDisputable), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+        assert_eq!("1, 0, 10, 10, false", engine.get_client_row(1));
+
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("resolve, 1, 1")));
+        assert_eq!("1, 10, 0, 10, false", engine.get_client_row(1));
+        assert_eq!(Ok(()), engine.conservation_check());
+    }
+
+    #[test]
+    fn test_withdrawal_within_overdraft_limit() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.set_overdraft_limit(1, "50.0".parse().unwrap()).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 10.0")).unwrap();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("withdrawal, 1, 2, 40.0")));
+        assert_eq!("1, -30, 0, -30, false", engine.get_client_row(1));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("withdrawal, 1, 3, 20.0")));
+        assert_eq!(Err(TransactionError::InsufficientFunds), engine.add_transaction(RawTransaction::new("withdrawal, 1, 4, 0.0001")));
+        assert_eq!("1, -50, 0, -50, false", engine.get_client_row(1));
+
+        let engine = InMemoryTransactionEngineBuilder::new().max_clients(0).build();
+        assert_eq!(Err(TransactionError::ClientLimitExceeded), engine.set_overdraft_limit(1, "1.0".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_for_each_client_visits_every_client() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 1.5")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 2, 2, 2.5")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 3, 3, 3.0")).unwrap();
+
+        let mut ids = Vec::new();
+        let mut total = Amount::ZERO;
+        engine.for_each_client(&mut |client| {
+            ids.push(client.id());
+            total += client.total();
+        });
+        ids.sort_unstable();
+        assert_eq!(vec![1, 2, 3], ids);
+        assert_eq!(Amount::from_units(70_000), total);
+    }
+
+    #[test]
+    fn test_resolve_and_chargeback_only_by_owner_of_disputed_transaction() {
+        let engine = InMemoryTransactionEngine::new();
+        for input in ["deposit, 1, 1, 1.0", "deposit, 2, 2, 2.0", "dispute, 1, 1", "dispute, 2, 2"] {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+
+        assert_eq!(Err(TransactionError::WrongClient), engine.add_transaction(RawTransaction::new("resolve, 2, 1")));
+        assert_eq!(Err(TransactionError::WrongClient), engine.add_transaction(RawTransaction::new("chargeback, 1, 2")));
+        assert_eq!("1, 0, 1, 1, false", engine.get_client_row(1));
+        assert_eq!("2, 0, 2, 2, false", engine.get_client_row(2));
+
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("resolve, 1, 1")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("chargeback, 2, 2")));
+        assert_eq!("1, 1, 0, 1, false", engine.get_client_row(1));
+        assert_eq!("2, 0, 0, 0, true", engine.get_client_row(2));
+    }
+
+    #[test]
+    fn test_chargeback_reversal() {
+        let engine = InMemoryTransactionEngine::new();
+        for input in ["deposit, 1, 1, 10.0", "deposit, 1, 2, 5.0", "withdrawal, 1, 3, 2.0", "dispute, 1, 1",
+            "chargeback, 1, 1"] {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+        assert_eq!("1, 3, 0, 3, true", engine.get_client_row(1));
+
+        assert_eq!(Err(TransactionError::NotChargedBack), engine.add_transaction(RawTransaction::new("chargeback_reversal, 2, 1")));
+        assert_eq!(Err(TransactionError::NotChargedBack), engine.add_transaction(RawTransaction::new("chargeback_reversal, 1, 2")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("chargeback_reversal, 1, 1")));
+        assert_eq!("1, 13, 0, 13, false", engine.get_client_row(1));
+        assert_eq!(Err(TransactionError::NotChargedBack), engine.add_transaction(RawTransaction::new("chargeback_reversal, 1, 1")));
+        assert_eq!(Ok(()), engine.conservation_check());
+
+        // withdrawal chargeback gave money back, reversal takes it out again.
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 3")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("chargeback, 1, 3")));
+        assert_eq!("1, 15, 0, 15, true", engine.get_client_row(1));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("chargeback_reversal, 1, 3")));
+        assert_eq!("1, 13, 0, 13, false", engine.get_client_row(1));
+        assert_eq!(Ok(()), engine.conservation_check());
+
+        // resolved transaction was never charged back.
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 1, 2")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("resolve, 1, 2")));
+        assert_eq!(Err(TransactionError::NotChargedBack), engine.add_transaction(RawTransaction::new("chargeback_reversal, 1, 2")));
+    }
+
+    #[test]
+    fn test_chargeback_reversal_replays_blocked_transactions() {
+        let mut engine = InMemoryTransactionEngine::new();
+        engine.enable_replay_log();
+        for input in ["deposit, 1, 1, 1.0", "dispute, 1, 1", "chargeback, 1, 1"] {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+        assert_eq!(Err(TransactionError::AccountLocked), engine.add_transaction(RawTransaction::new("deposit, 1, 2, 2.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("chargeback_reversal, 1, 1")));
+        assert!(engine.blocked_transactions().is_empty());
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 3, 3.0")));
+        assert_eq!("1, 6, 0, 6, false", engine.get_client_row(1));
+        assert_eq!(Ok(()), engine.conservation_check());
+        // blocked deposit comes right after reversal, ahead of later transactions.
+        let ids: Vec<u32> = engine.replay_log().iter().map(RawTransaction::transaction_id).collect();
+        assert_eq!(vec![1, 1, 1, 1, 2, 3], ids);
+    }
+
+    #[test]
+    fn test_locked_clients() {
+        let engine = InMemoryTransactionEngine::new();
+        for input in ["deposit, 1, 1, 1.0", "deposit, 2, 2, 1.0", "deposit, 3, 3, 1.0", "dispute, 2, 2", "dispute, 3, 3"] {
+            engine.add_transaction(RawTransaction::new(input)).unwrap();
+        }
+        assert!(engine.locked_clients().is_empty());
+
+        engine.add_transaction(RawTransaction::new("chargeback, 2, 2")).unwrap();
+        assert_eq!(vec![2], engine.locked_clients());
+    }
+
+    #[test]
+    fn test_hold_available_dispute_policy() {
+        let engine = InMemoryTransactionEngineBuilder::new().dispute_policy(DisputePolicy::HoldAvailable).build();
+        for input in ["deposit, 1, 1, 10.0", "withdrawal, 1, 2, 7.0", "dispute, 1, 1"] {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+        assert_eq!("1, 0, 3, 3, false", engine.get_client_row(1));
+        assert_eq!(Amount::from_units(70_000), engine.get_client(1).unwrap().pending_hold());
+
+        // deposits fill shortfall first.
+        engine.add_transaction(RawTransaction::new("deposit, 1, 3, 5.0")).unwrap();
+        assert_eq!("1, 0, 8, 8, false", engine.get_client_row(1));
+        engine.add_transaction(RawTransaction::new("deposit, 1, 4, 4.0")).unwrap();
+        assert_eq!("1, 2, 10, 12, false", engine.get_client_row(1));
+        assert_eq!(Amount::ZERO, engine.get_client(1).unwrap().pending_hold());
+        assert_eq!(Ok(()), engine.conservation_check());
+    }
+
+    #[test]
+    fn test_settling_partial_hold() {
+        let engine = InMemoryTransactionEngineBuilder::new().dispute_policy(DisputePolicy::HoldAvailable).build();
+        for input in ["deposit, 1, 1, 10.0", "withdrawal, 1, 2, 7.0", "dispute, 1, 1", "resolve, 1, 1"] {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+        assert_eq!("1, 3, 0, 3, false", engine.get_client_row(1));
+        assert_eq!(Amount::ZERO, engine.get_client(1).unwrap().pending_hold());
+
+        for input in ["deposit, 2, 3, 10.0", "withdrawal, 2, 4, 7.0", "dispute, 2, 3", "chargeback, 2, 3"] {
+            assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new(input)));
+        }
+        assert_eq!("2, -7, 0, -7, true", engine.get_client_row(2));
+        assert_eq!(Ok(()), engine.conservation_check());
+    }
+
+    #[test]
+    fn test_observer_gets_every_outcome() {
+        use std::sync::{Arc, Mutex};
+
+        let outcomes = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = InMemoryTransactionEngineBuilder::new().max_clients(2).build();
+        let observed = Arc::clone(&outcomes);
+        engine.set_observer(move |transaction, outcome| {
+            observed.lock().unwrap().push((transaction.transaction_id(), outcome));
+        });
+
+        for input in ["deposit, 1, 1, 1.0", "withdrawal, 1, 2, 5.0", "deposit, 1, 1, 1.0", "deposit, 2, 3, 1.0",
+            "deposit, 3, 4, 1.0", "dispute, 2, 1", "dispute, 1, 9", "resolve, 1, 1", "capture, 1, 1", "dispute, 1, 1",
+            "dispute, 1, 1", "chargeback_reversal, 1, 1", "chargeback, 1, 1", "deposit, 1, 5, 1.0"] {
+            let _ = engine.add_transaction(RawTransaction::new(input));
+        }
+
+        use TransactionOutcome::*;
+        assert_eq!(vec![(1, Applied), (2, RejectedInsufficientFunds), (1, RejectedDuplicate), (3, Applied),
+            (4, RejectedClientLimit), (1, RejectedWrongClient), (9, NotFound), (1, RejectedNotDisputed),
+            (1, RejectedNotPending), (1, Applied), (1, RejectedNotDisputable), (1, RejectedNotChargedBack),
+            (1, Applied), (5, RejectedLocked)], *outcomes.lock().unwrap());
+    }
+
+    /// Rows of every transaction type, over few clients and ids so that references often hit.
+    fn random_rows() -> impl Strategy<Value = Vec<String>> {
+        let transaction_types: Vec<TransactionType> = TransactionType::iterator().copied().collect();
+        let row = (prop::sample::select(transaction_types), 1..4u16, 1..20u32, 1..100_000i64)
+            .prop_map(|(transaction_type, client_id, transaction_id, units)| if transaction_type.requires_amount() {
+                format!("{}, {}, {}, {}", transaction_type.as_str(), client_id, transaction_id, Amount::from_units(units))
+            } else {
+                format!("{}, {}, {}", transaction_type.as_str(), client_id, transaction_id)
+            });
+        prop::collection::vec(row, 0..60)
+    }
+
+    proptest! {
+        #[test]
+        fn test_invariants_hold_for_random_input(
+            rows in random_rows(),
+            dispute_policy in prop::sample::select(vec![DisputePolicy::AllowNegativeAvailable,
+                DisputePolicy::RejectInsufficientAvailable, DisputePolicy::HoldAvailable]),
+        ) {
+            let engine = InMemoryTransactionEngineBuilder::new().dispute_policy(dispute_policy).build();
+            for row in &rows {
+                let _ = engine.add_transaction(RawTransaction::new(row));
+                prop_assert_eq!(Ok(()), engine.check_invariants(), "after {}", row);
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_invariants_reports_violations() {
+        let engine = InMemoryTransactionEngine::new();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 1, 1.0")));
+        assert_eq!(Ok(()), engine.check_invariants());
+
+        engine.clients.lock().unwrap().clear();
+        assert_eq!(Err(vec!["transaction 1 refers to missing client 1".to_string(),
+            "client balances differ from ledger by -1".to_string()]), engine.check_invariants());
+    }
+
+    #[test]
+    fn test_atomic_batch() {
+        let mut engine = InMemoryTransactionEngine::new();
+        let batch = vec![RawTransaction::new("deposit, 1, 1, 10.0"), RawTransaction::new("withdrawal, 1, 2, 4.0"),
+            RawTransaction::new("dispute, 1, 1")];
+        assert_eq!(Ok(()), engine.add_transactions_atomic(batch));
+        assert_eq!("1, -4, 10, 6, false", engine.get_client_row(1));
+
+        let stats = engine.stats();
+        let batch = vec![RawTransaction::new("resolve, 1, 1"), RawTransaction::new("deposit, 2, 3, 1.0"),
+            RawTransaction::new("withdrawal, 1, 4, 100.0"), RawTransaction::new("deposit, 1, 5, 1.0")];
+        assert_eq!(Err((2, TransactionError::InsufficientFunds)), engine.add_transactions_atomic(batch));
+        // neither resolve nor new client of failed batch is left behind.
+        assert_eq!("1, -4, 10, 6, false", engine.get_client_row(1));
+        assert_eq!(None, engine.get_client(2));
+        assert_eq!(stats, engine.stats());
+        assert_eq!(Ok(()), engine.check_invariants());
+
+        // transactions of failed batch can be applied again.
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("resolve, 1, 1")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 2, 3, 1.0")));
+    }
+
+    #[test]
+    fn test_replay_log_reproduces_engine() {
+        let mut engine = InMemoryTransactionEngineBuilder::new().defer_unknown_references(true).build();
+        assert!(engine.replay_log().is_empty());
+        engine.enable_replay_log();
+        for input in ["deposit, 1, 1, 5.0", "dispute, 2, 3", "deposit, 2, 2, 2.0", "withdrawal, 1, 4, 9.0",
+            "deposit, 2, 3, 1.0", "dispute, 1, 1", "chargeback, 1, 1", "deposit, 1, 5, 1.0", "withdrawal, 2, 6, 0.5"] {
+            let _ = engine.add_transaction(RawTransaction::new(input));
+        }
+        let batch = vec![RawTransaction::new("deposit, 3, 7, 1.0"), RawTransaction::new("withdrawal, 3, 8, 2.0")];
+        assert!(engine.add_transactions_atomic(batch).is_err());
+
+        // rejected, blocked and rolled back transactions are not logged, deferred dispute comes after its deposit.
+        let logged: Vec<String> = engine.replay_log().iter().map(RawTransaction::to_string).collect();
+        assert_eq!(vec!["deposit,1,1,5", "deposit,2,2,2", "deposit,2,3,1", "dispute,2,3,", "dispute,1,1,", "chargeback,1,1,",
+            "withdrawal,2,6,0.5"], logged);
+
+        let replayed = replay(&engine.replay_log());
+        let rows = |engine: &InMemoryTransactionEngine| {
+            let mut rows: Vec<String> = engine.snap_shot_clients().iter().map(Client::to_string).collect();
+            rows.sort();
+            rows
+        };
+        assert_eq!(rows(&engine), rows(&replayed));
+        assert_eq!(EngineStats { blocked_transactions: 0, ..engine.stats() }, replayed.stats());
+        let finalized = |engine: &InMemoryTransactionEngine| {
+            let mut output = Vec::new();
+            engine.write_finalized_csv(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(finalized(&engine), finalized(&replayed));
+    }
+
+    #[test]
+    fn test_zero_ids() {
+        // zero is a valid id by default.
+        let engine = InMemoryTransactionEngine::new();
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 0, 0, 1.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("dispute, 0, 0")));
+
+        let engine = InMemoryTransactionEngineBuilder::new().reject_zero_transaction_id(true).build();
+        assert_eq!(Err(TransactionError::ZeroId), engine.add_transaction(RawTransaction::new("deposit, 1, 0, 1.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 0, 1, 1.0")));
+        assert_eq!(Err(TransactionError::ZeroId), engine.add_transaction(RawTransaction::new("dispute, 0, 0")));
+
+        let engine = InMemoryTransactionEngineBuilder::new().reject_zero_client_id(true).build();
+        assert_eq!(Err(TransactionError::ZeroId), engine.add_transaction(RawTransaction::new("deposit, 0, 1, 1.0")));
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 0, 1.0")));
+        assert_eq!(None, engine.get_client(0));
+    }
+
+    #[test]
+    fn test_open_disputes() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 1.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 2, 2, 2.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 2, 3, 3.0")).unwrap();
+        assert!(engine.open_disputes().is_empty());
+
+        engine.add_transaction(RawTransaction::new("dispute, 1, 1")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 2, 2")).unwrap();
+        engine.add_transaction(RawTransaction::new("resolve, 1, 1")).unwrap();
+
+        let open = engine.open_disputes();
+        assert_eq!(1, open.len());
+        assert!(open[0].is_disputed());
+        assert_eq!((2, 2), (open[0].client_id(), open[0].transaction_id()));
+    }
+
+    #[test]
+    fn test_partial_disputes() {
+        // dispute without amount holds whole deposit.
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 100.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 1, 1")).unwrap();
+        assert_eq!("1, 0, 100, 100, false", engine.get_client(1).unwrap().to_string());
+        engine.add_transaction(RawTransaction::new("resolve, 1, 1")).unwrap();
+        assert_eq!("1, 100, 0, 100, false", engine.get_client(1).unwrap().to_string());
+        assert_eq!(Err(TransactionError::AlreadyFinalized), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+
+        // partial dispute holds only disputed part, and is resolved for that part.
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 100.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 1, 1, 30.0")).unwrap();
+        assert_eq!("1, 70, 30, 100, false", engine.get_client(1).unwrap().to_string());
+        assert_eq!(Err(TransactionError::NotDisputable), engine.add_transaction(RawTransaction::new("dispute, 1, 1, 10.0")));
+        engine.add_transaction(RawTransaction::new("resolve, 1, 1")).unwrap();
+        assert_eq!("1, 100, 0, 100, false", engine.get_client(1).unwrap().to_string());
+
+        // remainder can be disputed again, but not more than it. Dispute without amount takes all of remainder.
+        assert_eq!(Err(TransactionError::NotDisputable), engine.add_transaction(RawTransaction::new("dispute, 1, 1, 70.0001")));
+        engine.add_transaction(RawTransaction::new("dispute, 1, 1")).unwrap();
+        assert_eq!("1, 30, 70, 100, false", engine.get_client(1).unwrap().to_string());
+        engine.add_transaction(RawTransaction::new("chargeback, 1, 1")).unwrap();
+        assert_eq!("1, 30, 0, 30, true", engine.get_client(1).unwrap().to_string());
+        assert_eq!(Ok(()), engine.check_invariants());
+
+        // charged back part is what reversal brings back.
+        engine.add_transaction(RawTransaction::new("chargeback_reversal, 1, 1")).unwrap();
+        assert_eq!("1, 100, 0, 100, false", engine.get_client(1).unwrap().to_string());
+        assert_eq!(Ok(()), engine.check_invariants());
+    }
+
+    #[test]
+    fn test_partial_dispute_of_withdrawal() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 100.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("withdrawal, 1, 2, 40.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 1, 2, 10.0")).unwrap();
+        assert_eq!("1, 60, 10, 70, false", engine.get_client(1).unwrap().to_string());
+        engine.add_transaction(RawTransaction::new("chargeback, 1, 2")).unwrap();
+        assert_eq!("1, 70, 0, 70, true", engine.get_client(1).unwrap().to_string());
+        assert_eq!(Ok(()), engine.check_invariants());
+    }
+
+    #[test]
+    fn test_held_breakdown() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 10.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("withdrawal, 1, 2, 4.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 3, 2.5")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 2, 4, 1.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 1, 1, 3.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 1, 2")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 2, 4")).unwrap();
+
+        let breakdown = engine.held_breakdown(1);
+        assert_eq!(vec![(1, "3".parse().unwrap()), (2, "4".parse().unwrap())], breakdown);
+        let held: Amount = breakdown.iter().map(|(_, amount)| *amount).sum();
+        assert_eq!(engine.get_client(1).unwrap().held(), held);
+
+        engine.add_transaction(RawTransaction::new("resolve, 1, 2")).unwrap();
+        assert_eq!(vec![(1, "3".parse().unwrap())], engine.held_breakdown(1));
+        assert!(engine.held_breakdown(3).is_empty());
+    }
+
+    #[test]
+    fn test_reference_to_finalized_transaction() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 1.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 2, 2, 1.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 1, 1")).unwrap();
+        engine.add_transaction(RawTransaction::new("resolve, 1, 1")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 2, 2")).unwrap();
+        engine.add_transaction(RawTransaction::new("chargeback, 2, 2")).unwrap();
+
+        for finalized in ["dispute, 1, 1", "resolve, 1, 1", "chargeback, 1, 1"] {
+            assert_eq!(Err(TransactionError::AlreadyFinalized), engine.add_transaction(RawTransaction::new(finalized)));
+        }
+        assert_eq!(Err(TransactionError::TransactionNotFound), engine.add_transaction(RawTransaction::new("dispute, 1, 9")));
+        assert_ne!(TransactionError::AlreadyFinalized.to_string(), TransactionError::TransactionNotFound.to_string());
+
+        // finalized transaction is not waited for, even when unknown references are deferred.
+        let engine = InMemoryTransactionEngineBuilder::new().defer_unknown_references(true).build();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 1.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 1, 1")).unwrap();
+        engine.add_transaction(RawTransaction::new("resolve, 1, 1")).unwrap();
+        assert_eq!(Err(TransactionError::AlreadyFinalized), engine.add_transaction(RawTransaction::new("dispute, 1, 1")));
+        assert_eq!(Err(TransactionError::Deferred), engine.add_transaction(RawTransaction::new("dispute, 1, 9")));
+    }
+
+    #[test]
+    fn test_merge_engines_with_disjoint_clients() {
+        let mut first = InMemoryTransactionEngine::new();
+        first.add_transaction(RawTransaction::new("deposit, 1, 1, 10.0")).unwrap();
+        first.add_transaction(RawTransaction::new("dispute, 1, 1")).unwrap();
+        let _ = first.add_transaction(RawTransaction::new("withdrawal, 1, 2, 50.0"));
+        let second = InMemoryTransactionEngine::new();
+        second.add_transaction(RawTransaction::new("deposit, 2, 3, 5.0")).unwrap();
+        second.add_transaction(RawTransaction::new("dispute, 2, 3")).unwrap();
+        second.add_transaction(RawTransaction::new("chargeback, 2, 3")).unwrap();
+        let _ = second.add_transaction(RawTransaction::new("deposit, 2, 4, 1.0"));
+        second.add_transaction(RawTransaction::new("deposit, 5, 7, 1.0")).unwrap();
+        second.add_transaction(RawTransaction::new("dispute, 5, 7")).unwrap();
+        second.add_transaction(RawTransaction::new("resolve, 5, 7")).unwrap();
+
+        first.merge(second).unwrap();
+        let rows: Vec<String> = first.snap_shot_clients_sorted().iter().map(Client::to_string).collect();
+        assert_eq!(vec!["1, 0, 10, 10, false", "2, 0, 0, 0, true", "5, 1, 0, 1, false"], rows);
+        let stats = first.stats();
+        assert_eq!((1, 1, 2), (stats.active_transactions, stats.blocked_transactions, stats.finalized_transactions));
+        assert_eq!(Ok(()), first.check_invariants());
+        assert_eq!(Ok(()), first.conservation_check());
+        // merged transactions can be referred to like own ones.
+        assert_eq!(vec![(1, Amount::from_units(100_000))], first.held_breakdown(1));
+        assert_eq!(Ok(()), first.add_transaction(RawTransaction::new("resolve, 1, 1")));
+        assert_eq!(Err(TransactionError::AlreadyFinalized), first.add_transaction(RawTransaction::new("dispute, 5, 7")));
+        assert_eq!(vec![7], first.audit_log(5).iter().map(Transaction::transaction_id).collect::<Vec<u32>>());
+        assert_eq!(vec![1], first.audit_log(1).iter().map(Transaction::transaction_id).collect::<Vec<u32>>());
+        assert_eq!(1, first.blocked_transactions_for(2).len());
+
+        let colliding = InMemoryTransactionEngine::new();
+        colliding.add_transaction(RawTransaction::new("deposit, 2, 5, 1.0")).unwrap();
+        colliding.add_transaction(RawTransaction::new("deposit, 3, 6, 1.0")).unwrap();
+        assert_eq!(Err(TransactionError::ClientCollision(vec![2])), first.merge(colliding));
+        assert_eq!(None, first.get_client(3));
+
+        first.add_transaction(RawTransaction::new("deposit, 1, 8, 1.0")).unwrap();
+        let colliding = InMemoryTransactionEngine::new();
+        colliding.add_transaction(RawTransaction::new("deposit, 4, 8, 1.0")).unwrap();
+        assert_eq!(Err(TransactionError::DuplicateTransaction), first.merge(colliding));
+    }
+
+    #[test]
+    fn test_merge_rejects_colliding_finalized_ids() {
+        let charged_back = |client_id: u64| {
+            let engine = InMemoryTransactionEngine::new();
+            for input in ["deposit, {}, 7, 5.0", "dispute, {}, 7", "chargeback, {}, 7"] {
+                engine.add_transaction(RawTransaction::new(&input.replace("{}", &client_id.to_string()))).unwrap();
+            }
+            engine
+        };
+        let mut first = charged_back(1);
+        assert_eq!(Err(TransactionError::DuplicateTransaction), first.merge(charged_back(2)));
+        assert_eq!(None, first.get_client(2));
+        assert_eq!(1, first.stats().finalized_transactions);
+        assert_eq!(Ok(()), first.add_transaction(RawTransaction::new("chargeback_reversal, 1, 7")));
+        assert_eq!("1, 5, 0, 5, false", first.get_client_row(1));
+
+        // active id of one colliding with finalized id of other is caught as well.
+        let other = InMemoryTransactionEngine::new();
+        other.add_transaction(RawTransaction::new("deposit, 3, 7, 1.0")).unwrap();
+        assert_eq!(Err(TransactionError::DuplicateTransaction), first.merge(other));
+    }
+
+    #[test]
+    fn test_merge_keeps_blocked_within_max_and_replay_log_complete() {
+        let locked = |client_id: u64| {
+            let engine = InMemoryTransactionEngineBuilder::new().max_blocked_transactions(2).build();
+            for input in ["deposit, {}, {}1, 5.0", "dispute, {}, {}1", "chargeback, {}, {}1"] {
+                engine.add_transaction(RawTransaction::new(&input.replace("{}", &client_id.to_string()))).unwrap();
+            }
+            for input in ["deposit, {}, {}2, 1.0", "deposit, {}, {}3, 1.0"] {
+                let _ = engine.add_transaction(RawTransaction::new(&input.replace("{}", &client_id.to_string())));
+            }
+            engine
+        };
+        let mut first = locked(1);
+        first.merge(locked(2)).unwrap();
+        let ids: Vec<u32> = first.blocked_transactions().iter().map(RawTransaction::transaction_id).collect();
+        assert_eq!(vec![22, 23], ids);
+
+        let mut first = locked(1);
+        first.enable_replay_log();
+        assert_eq!(Err(TransactionError::MissingReplayLog), first.merge(locked(2)));
+        assert_eq!(None, first.get_client(2));
+    }
+
+    #[test]
+    fn test_accrue_held_fees() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 100.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 2, 50.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 1, 1")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 2, 3, 10.0")).unwrap();
+
+        engine.accrue_held_fees(0.01);
+        engine.accrue_held_fees(0.01);
+        assert_eq!("1, 50, 98.01, 148.01, false", engine.get_client(1).unwrap().to_string());
+        assert_eq!(Amount::from_units(19_900), engine.accrued_fees(1));
+        // client holding nothing is not charged.
+        assert_eq!("2, 10, 0, 10, false", engine.get_client(2).unwrap().to_string());
+        assert_eq!(Amount::ZERO, engine.accrued_fees(2));
+        assert_eq!(Ok(()), engine.check_invariants());
+
+        // disputed amount is released less fees.
+        engine.add_transaction(RawTransaction::new("resolve, 1, 1")).unwrap();
+        assert_eq!("1, 148.01, 0, 148.01, false", engine.get_client(1).unwrap().to_string());
+        assert_eq!(Amount::ZERO, engine.get_client(1).unwrap().held_fee());
+
+        // interest goes into held, and is charged back along with disputed amount.
+        engine.add_transaction(RawTransaction::new("dispute, 2, 3")).unwrap();
+        engine.accrue_held_fees(-0.00005);
+        assert_eq!("2, 0, 10.0005, 10.0005, false", engine.get_client(2).unwrap().to_string());
+        engine.accrue_held_fees(f64::NAN);
+        engine.accrue_held_fees(2.0);
+        engine.accrue_held_fees(f64::MAX);
+        assert_eq!("2, 0, 10.0005, 10.0005, false", engine.get_client(2).unwrap().to_string());
+        engine.add_transaction(RawTransaction::new("chargeback, 2, 3")).unwrap();
+        assert_eq!("2, 0, 0, 0, true", engine.get_client(2).unwrap().to_string());
+        assert_eq!(Ok(()), engine.check_invariants());
+    }
+
+    #[test]
+    fn test_deposits_to_locked_account() {
+        let locked_engine = |allow| {
+            let engine = InMemoryTransactionEngineBuilder::new().allow_deposits_when_locked(allow).build();
+            engine.add_transaction(RawTransaction::new("deposit, 1, 1, 10.0")).unwrap();
+            engine.add_transaction(RawTransaction::new("deposit, 1, 2, 5.0")).unwrap();
+            engine.add_transaction(RawTransaction::new("dispute, 1, 1")).unwrap();
+            engine.add_transaction(RawTransaction::new("chargeback, 1, 1")).unwrap();
+            engine
+        };
+
+        let engine = locked_engine(false);
+        assert_eq!(Err(TransactionError::AccountLocked), engine.add_transaction(RawTransaction::new("deposit, 1, 3, 2.0")));
+        assert_eq!("1, 5, 0, 5, true", engine.get_client(1).unwrap().to_string());
+        assert_eq!(1, engine.stats().blocked_transactions);
+
+        let engine = locked_engine(true);
+        assert_eq!(Ok(()), engine.add_transaction(RawTransaction::new("deposit, 1, 3, 2.0")));
+        assert_eq!("1, 7, 0, 7, true", engine.get_client(1).unwrap().to_string());
+        for blocked in ["withdrawal, 1, 4, 1.0", "dispute, 1, 3", "pending, 1, 5, 1.0"] {
+            assert_eq!(Err(TransactionError::AccountLocked), engine.add_transaction(RawTransaction::new(blocked)));
+        }
+        assert_eq!("1, 7, 0, 7, true", engine.get_client(1).unwrap().to_string());
+        assert_eq!(3, engine.stats().blocked_transactions);
+        assert_eq!(Ok(()), engine.check_invariants());
+    }
+
+    #[test]
+    fn test_withdrawal_does_not_create_client() {
+        let engine = InMemoryTransactionEngine::new();
+        assert_eq!(Err(TransactionError::InsufficientFunds), engine.add_transaction(RawTransaction::new("withdrawal, 1, 1, 1.0")));
+        assert!(engine.snap_shot_clients().is_empty());
+
+        // deposit that follows creates client as usual.
+        engine.add_transaction(RawTransaction::new("deposit, 1, 2, 2.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("withdrawal, 1, 3, 1.0")).unwrap();
+        assert_eq!(vec!["1, 1, 0, 1, false".to_string()], engine.snap_shot_clients().iter().map(Client::to_string).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_blocked_transactions() {
+        let engine = InMemoryTransactionEngine::new();
+        for client_id in [1, 2] {
+            engine.add_transaction(RawTransaction::new(&format!("deposit, {}, {}, 5.0", client_id, client_id))).unwrap();
+            engine.add_transaction(RawTransaction::new(&format!("dispute, {}, {}", client_id, client_id))).unwrap();
+            engine.add_transaction(RawTransaction::new(&format!("chargeback, {}, {}", client_id, client_id))).unwrap();
+        }
+        assert!(engine.blocked_transactions().is_empty());
+        for blocked in ["deposit, 1, 3, 1.0", "withdrawal, 2, 4, 1.0", "dispute, 1, 3"] {
+            assert_eq!(Err(TransactionError::AccountLocked), engine.add_transaction(RawTransaction::new(blocked)));
+        }
+
+        let rows = |transactions: Vec<RawTransaction>| transactions.iter().map(RawTransaction::to_string).collect::<Vec<_>>();
+        assert_eq!(vec!["deposit,1,3,1", "withdrawal,2,4,1", "dispute,1,3,"], rows(engine.blocked_transactions()));
+        assert_eq!(vec!["deposit,1,3,1", "dispute,1,3,"], rows(engine.blocked_transactions_for(1)));
+        assert_eq!(vec!["withdrawal,2,4,1"], rows(engine.blocked_transactions_for(2)));
+        assert!(engine.blocked_transactions_for(3).is_empty());
+    }
+
+    #[test]
+    fn test_client_id_beyond_u16() {
+        let client_id = u64::from(u16::MAX) + 1;
+        let engine = InMemoryTransactionEngine::new();
+        let deposit = RawTransaction::new(&format!("deposit, {}, 1, 2.0", client_id));
+        assert_eq!(client_id, deposit.client_id());
+        engine.add_transaction(deposit).unwrap();
+        engine.add_transaction(RawTransaction::new(&format!("dispute, {}, 1", u64::MAX))).unwrap_err();
+        engine.add_transaction(RawTransaction::new(&format!("deposit, {}, 2, 1.0", u64::MAX))).unwrap();
+        assert_eq!(format!("{}, 2, 0, 2, false", client_id), engine.get_client(client_id).unwrap().to_string());
+
+        let mut saved = Vec::new();
+        engine.save_snapshot(&mut saved).unwrap();
+        let loaded = InMemoryTransactionEngine::load_snapshot(saved.as_slice()).unwrap();
+        let client_ids: Vec<u64> = loaded.snap_shot_clients_sorted().iter().map(Client::id).collect();
+        assert_eq!(vec![client_id, u64::MAX], client_ids);
+        assert!(RawTransaction::parse("deposit, 18446744073709551616, 3, 1.0").is_err());
+    }
+
+    #[test]
+    fn test_engine_shared_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<InMemoryTransactionEngine>();
+
+        let engine = std::sync::Arc::new(InMemoryTransactionEngine::new());
+        let handles: Vec<_> = (1..=4u64).map(|client_id| {
+            let engine = std::sync::Arc::clone(&engine);
+            std::thread::spawn(move || {
+                for index in 0..100u64 {
+                    let transaction_id = client_id * 1000 + index;
+                    engine.add_transaction(RawTransaction::new(&format!("deposit, {}, {}, 1.0", client_id, transaction_id))).unwrap();
+                }
+                engine.add_transaction(RawTransaction::new(&format!("withdrawal, {}, {}, 40.0", client_id, client_id * 1000 + 999))).unwrap();
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        for client_id in 1..=4 {
+            assert_eq!("60", engine.get_client(client_id).unwrap().total().to_string());
+        }
+        assert_eq!(Ok(()), engine.check_invariants());
+        assert_eq!(404, engine.stats().active_transactions);
+    }
+
+    #[test]
+    fn test_deposit_overflowing_balance_is_rejected() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit,1,1,900000000000000")).unwrap();
+        assert_eq!(Err(TransactionError::Overflow), engine.add_transaction(RawTransaction::new("deposit,1,2,900000000000000")));
+        assert_eq!("1, 900000000000000, 0, 900000000000000, false", engine.get_client(1).unwrap().to_string());
+        assert_eq!(Ok(()), engine.check_invariants());
+
+        // ledger of engine overflows even though balance of each client fits.
+        assert_eq!(Err(TransactionError::Overflow), engine.add_transaction(RawTransaction::new("deposit,2,3,900000000000000")));
+        assert_eq!(None, engine.get_client(2));
+    }
+
+    #[test]
+    fn test_fee_rounding_follows_config() {
+        let fees = |rounding| {
+            let engine = InMemoryTransactionEngineBuilder::new().with_rounding(rounding).build();
+            engine.add_transaction(RawTransaction::new("deposit, 1, 1, 0.0005")).unwrap();
+            engine.add_transaction(RawTransaction::new("dispute, 1, 1")).unwrap();
+            engine.add_transaction(RawTransaction::new("deposit, 2, 2, 0.0003")).unwrap();
+            engine.add_transaction(RawTransaction::new("dispute, 2, 2")).unwrap();
+            engine.accrue_held_fees(0.5);
+            (engine.accrued_fees(1).units(), engine.accrued_fees(2).units())
+        };
+        // fees of 2.5 and 1.5 ten-thousandths.
+        assert_eq!((3, 2), fees(RoundingMode::HalfUp));
+        assert_eq!((2, 2), fees(RoundingMode::HalfEven));
+        assert_eq!((2, 1), fees(RoundingMode::Truncate));
+        assert_eq!(RoundingMode::HalfEven, InMemoryTransactionEngine::new().config().rounding);
+    }
+}
@@ -1,36 +1,175 @@
-use std::{fs::File,
-    io::{BufReader, BufRead}};
+use std::{fs::{self, File},
+    io::{self, BufRead, BufReader, IsTerminal, Read},
+    path::Path,
+    process};
 
 use clap::{Command, Arg};
+use flate2::read::GzDecoder;
 use payment_engine::{
-    InMemoryTransactionEngine,
+    InMemoryTransactionEngineBuilder,
     TransactionEngine,
-    transaction::{validator::is_valid_input, Transaction}};
+    processor::{run_strict, run_with_options, validate_files_with_options, RunReport},
+    amount::RoundingMode,
+    transaction::options::ParseOptions,
+    report::{write_grouped_by_locked, write_json_report, write_report, write_report_with_status, write_totals}};
 
 fn main() {
+    env_logger::init();
+
     let matches = Command::new("Payment Engine")
         .arg(
-            Arg::new("file").index(1).required(true)
+            Arg::new("file").index(1).multiple_values(true)
+                .help("Input files, processed one after other against same engine. `-` or none reads stdin")
+        )
+        .arg(
+            Arg::new("group-by-locked").long("group-by-locked")
+                .conflicts_with_all(&["format", "status-column", "totals-only"])
+                .help("Emit locked accounts first, separated from active accounts")
+        )
+        .arg(
+            Arg::new("format").long("format").takes_value(true)
+                .possible_values(["csv", "json"])
+                .conflicts_with_all(&["group-by-locked", "status-column", "totals-only"])
+                .help("Format of client balances written to stdout, csv unless given")
+        )
+        .arg(
+            Arg::new("status-column").long("status-column")
+                .conflicts_with_all(&["group-by-locked", "format", "totals-only"])
+                .help("Append status of account, active, frozen or overdrawn, to csv output")
+        )
+        .arg(
+            Arg::new("totals-only").long("totals-only")
+                .conflicts_with_all(&["group-by-locked", "format", "status-column"])
+                .help("Write only client id and total balance of every client, as csv")
+        )
+        .arg(
+            Arg::new("check").long("check")
+                .help("Only report rows that are invalid or would be rejected, exiting non zero if there are any")
+        )
+        .arg(
+            Arg::new("lenient-amounts").long("lenient-amounts")
+                .help("Accept amounts with currency symbol and thousands separators, e.g. \"$1,234.56\" quoted")
+        )
+        .arg(
+            Arg::new("rounding").long("rounding").takes_value(true)
+                .possible_values(["half-up", "half-even", "truncate"])
+                .help("How amounts with more than four decimal places are rounded, half-even unless given")
+        )
+        .arg(
+            Arg::new("delimiter").long("delimiter").takes_value(true)
+                .possible_values(["comma", "tab", "semicolon"]).default_value("comma")
+                .help("Separator of fields in input files")
+        )
+        .arg(
+            Arg::new("strict").long("strict")
+                .help("Stop at first line which can not be read or is not a valid transaction, exiting non zero")
+        )
+        .arg(
+            Arg::new("report").long("report").takes_value(true)
+                .help("Write json report of the run to given path")
         )
         .get_matches();
-    let transaction_file_name = matches.value_of("file").unwrap();
-    let transaction_file = File::open(transaction_file_name).unwrap();
-    let transaction_reader = BufReader::new(transaction_file);
-
-    let mut transaction_engine = InMemoryTransactionEngine::new();
-    
-    for transaction in transaction_reader.lines() {
-        if let Ok(transaction) = transaction {
-            if !is_valid_input(&transaction) {
-                continue;
+    let transaction_file_names: Vec<&str> = match matches.values_of("file") {
+        Some(file_names) => file_names.collect(),
+        None if io::stdin().is_terminal() => {
+            eprintln!("No input file given, pass one or pipe transactions through stdin");
+            process::exit(2);
+        },
+        None => vec![STDIN],
+    };
+    let transaction_readers: Vec<Box<dyn BufRead>> = transaction_file_names.iter()
+        .map(|file_name| match open_input(file_name) {
+            Ok(reader) => reader,
+            Err(err) => {
+                eprintln!("{}: {}", file_name, err);
+                process::exit(2);
+            },
+        })
+        .collect();
+
+    let rounding = match matches.value_of("rounding") {
+        Some("half-up") => RoundingMode::HalfUp,
+        Some("truncate") => RoundingMode::Truncate,
+        _ => RoundingMode::HalfEven,
+    };
+    let mut transaction_engine = InMemoryTransactionEngineBuilder::new().with_rounding(rounding).build();
+    let delimiter = match matches.value_of("delimiter") {
+        Some("tab") => '\t',
+        Some("semicolon") => ';',
+        _ => ',',
+    };
+    let options = ParseOptions { delimiter, lenient_amounts: matches.is_present("lenient-amounts"), ..ParseOptions::default() };
+    if matches.is_present("check") {
+        // rows are only read, rounding them as engine would.
+        let options = ParseOptions { rounding: Some(transaction_engine.config().rounding), ..options };
+        let errors = validate_files_with_options(transaction_readers, &options);
+        for (file_name, errors) in transaction_file_names.iter().zip(&errors) {
+            for (line, reason) in errors {
+                if transaction_file_names.len() > 1 {
+                    println!("{}: line {}: {}", file_name, line, reason);
+                } else {
+                    println!("line {}: {}", line, reason);
+                }
             }
-            let transaction = Transaction::new(&transaction);
-            transaction_engine.add_transaction(transaction);
+        }
+        if errors.iter().any(|errors| !errors.is_empty()) {
+            process::exit(1);
+        }
+        return;
+    }
+
+    let mut report = RunReport::default();
+    for (file_name, transaction_reader) in transaction_file_names.iter().zip(transaction_readers) {
+        if !matches.is_present("strict") {
+            report.merge(run_with_options(transaction_reader, &mut transaction_engine, &options));
+            continue;
+        }
+        match run_strict(transaction_reader, &mut transaction_engine, &options) {
+            Ok(file_report) => report.merge(file_report),
+            Err(invalid) => {
+                eprintln!("{}: line {}: {}: {}", file_name, invalid.line, invalid.reason, invalid.input);
+                process::exit(1);
+            },
         }
     }
+    eprintln!("{}", report.summary);
+    if let Some(report_path) = matches.value_of("report") {
+        if let Err(err) = fs::write(report_path, report.to_json().unwrap()) {
+            eprintln!("{}: {}", report_path, err);
+            process::exit(2);
+        }
+    }
+
+    if matches.value_of("format") == Some("json") {
+        write_json_report(&transaction_engine, &mut io::stdout().lock()).unwrap();
+    } else if matches.is_present("group-by-locked") {
+        write_grouped_by_locked(&transaction_engine.snap_shot_clients(), &mut io::stdout().lock()).unwrap();
+    } else if matches.is_present("totals-only") {
+        write_totals(&transaction_engine, &mut io::stdout().lock()).unwrap();
+    } else if matches.is_present("status-column") {
+        write_report_with_status(&transaction_engine, &mut io::stdout().lock()).unwrap();
+    } else {
+        write_report(&transaction_engine, &mut io::stdout().lock()).unwrap();
+    }
+
+}
+
+/// Input file name standing for stdin.
+const STDIN: &str = "-";
+
+/// Starting bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
 
-    println!("{}", "client,available,held,total,locked");
-    for client in transaction_engine.snap_shot_clients() {
-        println!("{}", client);
+/// Reader over content of file, or of stdin for `-`, decompressed if it is gzipped,
+/// whether or not its name ends with `.gz`.
+fn open_input(file_name: &str) -> io::Result<Box<dyn BufRead>> {
+    let input: Box<dyn Read> = if file_name == STDIN { Box::new(io::stdin()) } else { Box::new(File::open(file_name)?) };
+    let mut reader = BufReader::new(input);
+    let gzipped = Path::new(file_name).extension().is_some_and(|extension| extension == "gz")
+        || reader.fill_buf()?.starts_with(&GZIP_MAGIC);
+    if gzipped {
+        Ok(Box::new(BufReader::new(GzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
     }
 }
@@ -0,0 +1,112 @@
+use std::fmt::Write;
+
+use crate::{transaction::{Transaction, TransactionType}, InMemoryTransactionEngine, TransactionOutcome};
+
+/// Label of every outcome counted as rejection, in order rejections are written.
+const REJECTION_REASONS: [(TransactionOutcome, &str); 14] = [
+    (TransactionOutcome::RejectedLocked, "locked"),
+    (TransactionOutcome::RejectedDuplicate, "duplicate"),
+    (TransactionOutcome::RejectedClientLimit, "client_limit"),
+    (TransactionOutcome::RejectedInsufficientFunds, "insufficient_funds"),
+    (TransactionOutcome::RejectedWrongClient, "wrong_client"),
+    (TransactionOutcome::RejectedNotDisputable, "not_disputable"),
+    (TransactionOutcome::RejectedNotDisputed, "not_disputed"),
+    (TransactionOutcome::RejectedNotPending, "not_pending"),
+    (TransactionOutcome::RejectedNotChargedBack, "not_charged_back"),
+    (TransactionOutcome::RejectedOutOfOrder, "out_of_order"),
+    (TransactionOutcome::RejectedZeroId, "zero_id"),
+    (TransactionOutcome::NotFound, "not_found"),
+    (TransactionOutcome::RejectedFinalized, "finalized"),
+    (TransactionOutcome::RejectedOverflow, "overflow"),
+];
+
+impl InMemoryTransactionEngine {
+    /// Counters of engine in Prometheus text exposition format.
+    /// 1. payment_engine_transactions_total - applied transactions, by type.
+    /// 2. payment_engine_rejections_total - transactions not applied, by reason. Deferred ones are counted once rejected.
+    /// 3. payment_engine_disputes_open - disputes yet to be resolved or charged back.
+    /// 4. payment_engine_clients_locked - clients whose account is locked.
+    ///
+    /// Every type and reason is written, even when zero, so series do not come and go.
+    pub fn metrics_text(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let clients = self.clients.lock().unwrap();
+        let mut text = String::new();
+
+        write_header(&mut text, "payment_engine_transactions_total", "Transactions applied, by type.", "counter");
+        for transaction_type in TransactionType::iterator() {
+            let applied: usize = state.type_counts.values()
+                .filter_map(|counts| counts.get(transaction_type))
+                .sum();
+            let _ = writeln!(text, "payment_engine_transactions_total{{type=\"{}\"}} {}", transaction_type.as_str(), applied);
+        }
+
+        write_header(&mut text, "payment_engine_rejections_total", "Transactions not applied, by reason.", "counter");
+        for (outcome, reason) in REJECTION_REASONS {
+            let rejected = state.rejection_counts.get(&outcome).copied().unwrap_or(0);
+            let _ = writeln!(text, "payment_engine_rejections_total{{reason=\"{}\"}} {}", reason, rejected);
+        }
+
+        write_header(&mut text, "payment_engine_disputes_open", "Disputes yet to be resolved or charged back.", "gauge");
+        let open = state.tranasctions.values().filter(|transaction| Transaction::is_disputed(transaction)).count();
+        let _ = writeln!(text, "payment_engine_disputes_open {}", open);
+
+        write_header(&mut text, "payment_engine_clients_locked", "Clients whose account is locked.", "gauge");
+        let locked = clients.values().filter(|client| client.is_locked()).count();
+        let _ = writeln!(text, "payment_engine_clients_locked {}", locked);
+        text
+    }
+}
+
+fn write_header(text: &mut String, name: &str, help: &str, metric_type: &str) {
+    let _ = writeln!(text, "# HELP {} {}", name, help);
+    let _ = writeln!(text, "# TYPE {} {}", name, metric_type);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{transaction::RawTransaction, TransactionEngine};
+
+    /// Name, with labels, and value of every sample line, after checking each line is well formed.
+    fn parse(text: &str) -> Vec<(String, f64)> {
+        let mut samples = Vec::new();
+        for line in text.lines() {
+            if let Some(comment) = line.strip_prefix("# ") {
+                let parts: Vec<&str> = comment.splitn(3, ' ').collect();
+                assert!(matches!(parts.as_slice(), ["HELP", _, _] | ["TYPE", _, "counter" | "gauge"]), "{}", line);
+                continue;
+            }
+            let (name, value) = line.rsplit_once(' ').unwrap_or_else(|| panic!("no value in {}", line));
+            let metric = name.split('{').next().unwrap();
+            assert!(metric.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'), "{}", line);
+            assert_eq!(name.contains('{'), name.ends_with('}'), "{}", line);
+            samples.push((name.to_string(), value.parse::<f64>().unwrap()));
+        }
+        samples
+    }
+
+    #[test]
+    fn test_metrics_text() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 10.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 2, 2, 10.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 3, 3, 10.0")).unwrap();
+        let _ = engine.add_transaction(RawTransaction::new("withdrawal, 1, 4, 100.0"));
+        let _ = engine.add_transaction(RawTransaction::new("dispute, 1, 9"));
+        engine.add_transaction(RawTransaction::new("dispute, 1, 1")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 2, 2")).unwrap();
+        engine.add_transaction(RawTransaction::new("chargeback, 2, 2")).unwrap();
+
+        let samples = parse(&engine.metrics_text());
+        let value = |name: &str| samples.iter().find(|(sample, _)| sample == name).map(|(_, value)| *value);
+        assert_eq!(Some(3.0), value("payment_engine_transactions_total{type=\"deposit\"}"));
+        assert_eq!(Some(2.0), value("payment_engine_transactions_total{type=\"dispute\"}"));
+        assert_eq!(Some(0.0), value("payment_engine_transactions_total{type=\"withdrawal\"}"));
+        assert_eq!(Some(1.0), value("payment_engine_rejections_total{reason=\"insufficient_funds\"}"));
+        assert_eq!(Some(1.0), value("payment_engine_rejections_total{reason=\"not_found\"}"));
+        assert_eq!(Some(0.0), value("payment_engine_rejections_total{reason=\"locked\"}"));
+        assert_eq!(Some(1.0), value("payment_engine_disputes_open"));
+        assert_eq!(Some(1.0), value("payment_engine_clients_locked"));
+    }
+}
@@ -0,0 +1,96 @@
+use std::{sync::mpsc, thread};
+
+use crate::{account::Client, config::EngineConfig, transaction::RawTransaction, InMemoryTransactionEngine, TransactionEngine};
+
+/// Processes transactions on config.workers threads. Each worker owns its own engine and gets
+/// every transaction of a client, so transactions of a client are applied in the order they arrive.
+/// Snapshot of all workers is merged once input is exhausted.
+///
+/// Every transaction, including dispute, resolve and chargeback, only ever touches balances of its own client,
+/// so order in which transactions of different clients interleave does not change any balance.
+/// Only order within a client matters, and that is kept by channel of the worker owning client.
+/// `examples/parallel_bench.rs` compares this against serial engine.
+pub fn process_parallel<I>(config: &EngineConfig, transactions: I) -> Vec<Client>
+where
+    I: IntoIterator<Item = RawTransaction>,
+{
+    let workers = config.workers.max(1);
+    thread::scope(|scope| {
+        let mut senders = Vec::with_capacity(workers);
+        let mut handles = Vec::with_capacity(workers);
+        for _ in 0..workers {
+            let (sender, receiver) = mpsc::channel::<RawTransaction>();
+            let engine = InMemoryTransactionEngine::with_config(config.clone());
+            handles.push(scope.spawn(move || {
+                for transaction in receiver {
+                    let _ = engine.add_transaction(transaction);
+                }
+                engine.snap_shot_clients()
+            }));
+            senders.push(sender);
+        }
+
+        for transaction in transactions {
+            let worker = transaction.client_id() as usize % workers;
+            senders[worker].send(transaction).unwrap();
+        }
+        // closing channels lets workers finish.
+        drop(senders);
+
+        handles.into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::InMemoryTransactionEngineBuilder;
+
+    fn transactions() -> Vec<RawTransaction> {
+        let mut transactions = Vec::new();
+        for client_id in 1..=20u16 {
+            let base = client_id as u32 * 10;
+            transactions.push(RawTransaction::new(&format!("deposit, {}, {}, 10.0", client_id, base)));
+            transactions.push(RawTransaction::new(&format!("withdrawal, {}, {}, 2.5", client_id, base + 1)));
+            transactions.push(RawTransaction::new(&format!("dispute, {}, {}", client_id, base)));
+            if client_id % 2 == 0 {
+                transactions.push(RawTransaction::new(&format!("chargeback, {}, {}", client_id, base)));
+                transactions.push(RawTransaction::new(&format!("deposit, {}, {}, 1.0", client_id, base + 2)));
+            }
+        }
+        transactions
+    }
+
+    fn sorted_rows(clients: Vec<Client>) -> Vec<String> {
+        let mut rows: Vec<String> = clients.iter().map(|client| client.to_string()).collect();
+        rows.sort();
+        rows
+    }
+
+    #[test]
+    fn test_same_result_with_one_and_many_workers() {
+        let single = InMemoryTransactionEngineBuilder::new().with_workers(1);
+        let many = InMemoryTransactionEngineBuilder::new().with_workers(4);
+
+        let single_rows = sorted_rows(process_parallel(single.config(), transactions()));
+        let many_rows = sorted_rows(process_parallel(many.config(), transactions()));
+        assert_eq!(20, single_rows.len());
+        assert_eq!(single_rows, many_rows);
+    }
+
+    #[test]
+    fn test_order_within_client_is_kept() {
+        let config = InMemoryTransactionEngineBuilder::new().with_workers(3);
+        let transactions = vec![
+            RawTransaction::new("withdrawal, 1, 1, 1.0"),
+            RawTransaction::new("deposit, 2, 2, 1.0"),
+            RawTransaction::new("deposit, 1, 3, 1.0"),
+            RawTransaction::new("withdrawal, 2, 4, 1.0"),
+        ];
+        let rows = sorted_rows(process_parallel(config.config(), transactions));
+        // withdrawal of client 1 came before its deposit, so it failed.
+        assert_eq!(vec!["1, 1, 0, 1, false", "2, 0, 0, 0, false"], rows);
+    }
+}
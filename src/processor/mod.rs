@@ -0,0 +1,606 @@
+use std::{collections::BTreeMap, fmt::Display, io::{self, BufRead}, ops::ControlFlow, sync::mpsc, thread};
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::Serialize;
+
+use crate::{
+    transaction::{options::{ColumnMapping, ParseOptions}, RawTransaction},
+    InMemoryTransactionEngine,
+    TransactionEngine};
+
+/// Counts of what happened to input rows during a run.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct RunSummary {
+    /// rows applied by engine.
+    pub accepted: usize,
+    /// well formed rows which engine refused, e.g. withdrawal without enough funds.
+    pub rejected: usize,
+    /// rows which could not be read or are not valid transactions.
+    pub invalid: usize,
+}
+
+impl RunSummary {
+    /// true if any row of input could not be read or understood.
+    pub fn had_errors(&self) -> bool {
+        self.invalid > 0
+    }
+}
+
+impl Display for RunSummary {
+    /// One line health check of a run, e.g. `accepted: 3, rejected invalid: 1, rejected business: 2`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "accepted: {}, rejected invalid: {}, rejected business: {}", self.accepted, self.invalid, self.rejected)
+    }
+}
+
+/// Figures about input itself, as opposed to its outcome.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ProcessingStats {
+    pub lines_read: usize,
+    /// blank, comment and header lines.
+    pub lines_skipped: usize,
+    /// applied rows keyed by their transaction type.
+    pub applied_by_type: BTreeMap<String, usize>,
+}
+
+/// Row that could not be read or is not a valid transaction. line is 1 based.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RowError {
+    pub line: usize,
+    pub input: String,
+    pub reason: String,
+}
+
+/// Everything known about a run, meant to be written out as json.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct RunReport {
+    pub summary: RunSummary,
+    pub stats: ProcessingStats,
+    pub errors: Vec<RowError>,
+}
+
+impl RunReport {
+    /// Adds up report of a following run on same engine, e.g. of next input file.
+    /// Line numbers of errors stay relative to input they came from.
+    pub fn merge(&mut self, other: RunReport) {
+        self.summary.accepted += other.summary.accepted;
+        self.summary.rejected += other.summary.rejected;
+        self.summary.invalid += other.summary.invalid;
+        self.stats.lines_read += other.stats.lines_read;
+        self.stats.lines_skipped += other.stats.lines_skipped;
+        for (transaction_type, count) in other.stats.applied_by_type {
+            *self.stats.applied_by_type.entry(transaction_type).or_insert(0) += count;
+        }
+        self.errors.extend(other.errors);
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Reads transactions line by line and applies them to engine.
+/// Blank lines, comment lines starting with `#` and header line are skipped without being counted. Header is first non blank line naming
+/// `type`, `client` and `tx` columns, and rows after it are read by those names, whatever their order.
+/// First non blank line starting with `type` is skipped as header as well, but leaves columns as default.
+pub fn run<R: BufRead>(reader: R, engine: &mut impl TransactionEngine) -> RunSummary {
+    run_with_report(reader, engine).summary
+}
+
+/// Builds engine with default config and applies every transaction of reader to it.
+/// Invalid lines are skipped, same as run, and counted in returned summary.
+pub fn process<R: BufRead>(reader: R) -> (InMemoryTransactionEngine, RunSummary) {
+    let mut engine = InMemoryTransactionEngine::new();
+    let summary = run(reader, &mut engine);
+    (engine, summary)
+}
+
+/// Same as run, but also collects stats and row errors.
+pub fn run_with_report<R: BufRead>(reader: R, engine: &mut impl TransactionEngine) -> RunReport {
+    run_with_options(reader, engine, &ParseOptions::default())
+}
+
+/// Same as run_with_report, reading rows as per options. Columns named by header of reader take over columns of options.
+/// Amounts are rounded as per engine, see TransactionEngine::rounding.
+pub fn run_with_options<R: BufRead>(reader: R, engine: &mut impl TransactionEngine, options: &ParseOptions) -> RunReport {
+    let options = options_for(engine, options);
+    let mut report = RunReport::default();
+    let mut stats = ProcessingStats::default();
+    read_rows(reader, &options, &mut stats, |line, input, transaction| {
+        apply_row(engine, &mut report, line, input, transaction);
+        ControlFlow::Continue(())
+    });
+    report.stats.lines_read = stats.lines_read;
+    report.stats.lines_skipped = stats.lines_skipped;
+    report
+}
+
+/// Same as run_with_options, except that it stops at first row which can not be read or is not a valid transaction,
+/// returning that row as error. Rows before it stay applied to engine. Rows engine rejects do not stop it.
+pub fn run_strict<R: BufRead>(reader: R, engine: &mut impl TransactionEngine, options: &ParseOptions) -> Result<RunReport, RowError> {
+    let options = options_for(engine, options);
+    let mut report = RunReport::default();
+    let mut stats = ProcessingStats::default();
+    let mut invalid = None;
+    read_rows(reader, &options, &mut stats, |line, input, transaction| {
+        if let Err(reason) = transaction {
+            invalid = Some(RowError { line, input: input.to_string(), reason });
+            return ControlFlow::Break(());
+        }
+        apply_row(engine, &mut report, line, input, transaction);
+        ControlFlow::Continue(())
+    });
+    if let Some(invalid) = invalid {
+        return Err(invalid);
+    }
+    report.stats.lines_read = stats.lines_read;
+    report.stats.lines_skipped = stats.lines_skipped;
+    Ok(report)
+}
+
+/// Rows handed to a parser in one go, so that channels are not hit once per row.
+const PIPELINE_BATCH_SIZE: usize = 1024;
+
+/// Batches a parser can have waiting on either side, bounding memory when reading outpaces applying.
+const PIPELINE_BATCHES_IN_FLIGHT: usize = 4;
+
+/// Rows of a batch, as line number with trimmed input or reason it could not be read, along with options to parse them by.
+type RowBatch = (ParseOptions, Vec<(usize, Result<String, String>)>);
+
+/// Same as run_with_options, with lines read on one thread and parsed on parsers threads while engine applies them
+/// on calling thread, so reading and parsing overlap with applying.
+///
+/// Rows are applied in exactly the order they appear in reader, same as run_with_options, so report and engine end up the same.
+/// Batch n of rows goes to parser n % parsers and results are taken back in that same round robin order, which keeps
+/// input order without any reordering buffer. Only parsing runs in parallel, engine still sees one transaction at a time.
+/// `examples/parallel_bench.rs` compares this against run_with_options.
+pub fn run_pipelined<R>(reader: R, engine: &mut impl TransactionEngine, options: &ParseOptions, parsers: usize) -> RunReport
+where
+    R: BufRead + Send,
+{
+    let options = &options_for(engine, options);
+    let parsers = parsers.max(1);
+    let mut report = RunReport::default();
+    let stats = thread::scope(|scope| {
+        let mut senders = Vec::with_capacity(parsers);
+        let mut receivers = Vec::with_capacity(parsers);
+        for _ in 0..parsers {
+            let (sender, rows) = mpsc::sync_channel::<RowBatch>(PIPELINE_BATCHES_IN_FLIGHT);
+            let (parsed_sender, parsed) = mpsc::sync_channel(PIPELINE_BATCHES_IN_FLIGHT);
+            scope.spawn(move || {
+                for (options, rows) in rows {
+                    let parsed: Vec<_> = rows.into_iter()
+                        .map(|(line, input)| match input {
+                            Ok(input) => {
+                                let transaction = parse_row(&input, &options);
+                                (line, input, transaction)
+                            },
+                            Err(reason) => (line, String::new(), Err(reason)),
+                        })
+                        .collect();
+                    if parsed_sender.send(parsed).is_err() {
+                        return;
+                    }
+                }
+            });
+            senders.push(sender);
+            receivers.push(parsed);
+        }
+
+        let lines = scope.spawn(move || {
+            let mut stats = ProcessingStats::default();
+            let mut batch: RowBatch = (options.clone(), Vec::with_capacity(PIPELINE_BATCH_SIZE));
+            let mut batches = 0;
+            scan_rows(reader, options, &mut stats, |line, input, options| {
+                if batch.1.is_empty() {
+                    // header, if any, comes before first row, so options of first row hold for whole batch.
+                    batch.0 = options.clone();
+                }
+                batch.1.push((line, input.map(str::to_string)));
+                if batch.1.len() == PIPELINE_BATCH_SIZE {
+                    let _ = senders[batches % parsers].send(std::mem::replace(&mut batch, (options.clone(), Vec::new())));
+                    batches += 1;
+                }
+                ControlFlow::Continue(())
+            });
+            if !batch.1.is_empty() {
+                let _ = senders[batches % parsers].send(batch);
+            }
+            // closing channels lets parsers finish.
+            stats
+        });
+
+        // parser of next batch closing its channel means there is no next batch.
+        for batches in 0.. {
+            let parsed = match receivers[batches % parsers].recv() {
+                Ok(parsed) => parsed,
+                Err(_) => break,
+            };
+            for (line, input, transaction) in parsed {
+                apply_row(engine, &mut report, line, &input, transaction);
+            }
+        }
+        lines.join().unwrap()
+    });
+    report.stats.lines_read = stats.lines_read;
+    report.stats.lines_skipped = stats.lines_skipped;
+    report
+}
+
+/// Applies row to engine, counting its outcome in report.
+fn apply_row(engine: &mut impl TransactionEngine, report: &mut RunReport, line: usize, input: &str,
+    transaction: Result<(RawTransaction, Option<DateTime<Utc>>), String>) {
+    let (transaction, timestamp) = match transaction {
+        Ok(transaction) => transaction,
+        Err(reason) => {
+            warn!(line; "line {}: Skipping invalid input {} as {}", line, input, reason);
+            report.summary.invalid += 1;
+            report.errors.push(RowError { line, input: input.to_string(), reason });
+            return;
+        },
+    };
+    let transaction_type = transaction.transaction_type();
+    match engine.add_transaction_at(transaction, timestamp) {
+        Ok(()) => {
+            report.summary.accepted += 1;
+            *report.stats.applied_by_type.entry(transaction_type.as_str().to_string()).or_insert(0) += 1;
+        },
+        Err(err) => {
+            // engine logs why it refused transaction, but does not know where in input it came from.
+            warn!(line; "line {}: Skipping {} as {}", line, input, err);
+            report.summary.rejected += 1;
+        },
+    }
+}
+
+/// Dry run of reader on a scratch engine, nothing outside of it is touched.
+/// Returns line number and reason for every row which is invalid or which engine would reject,
+/// e.g. for insufficient funds, locked account or duplicate transaction id.
+pub fn validate_file<R: BufRead>(reader: R) -> Vec<(usize, String)> {
+    validate_files([reader]).pop().unwrap_or_default()
+}
+
+/// Same as validate_file for readers processed one after other on same scratch engine, so that
+/// transaction of a reader can refer to one of an earlier reader. Errors are returned per reader, in their order.
+pub fn validate_files<I, R>(readers: I) -> Vec<Vec<(usize, String)>>
+where
+    I: IntoIterator<Item = R>,
+    R: BufRead,
+{
+    validate_files_with_options(readers, &ParseOptions::default())
+}
+
+/// Same as validate_files, reading rows as per options.
+pub fn validate_files_with_options<I, R>(readers: I, options: &ParseOptions) -> Vec<Vec<(usize, String)>>
+where
+    I: IntoIterator<Item = R>,
+    R: BufRead,
+{
+    let engine = InMemoryTransactionEngine::new();
+    readers.into_iter()
+        .map(|reader| {
+            let mut errors = Vec::new();
+            read_rows(reader, options, &mut ProcessingStats::default(), |line, _, transaction| {
+                match transaction {
+                    Ok((transaction, timestamp)) => {
+                        if let Err(err) = engine.add_transaction_at(transaction, timestamp) {
+                            errors.push((line, err.to_string()));
+                        }
+                    },
+                    Err(reason) => errors.push((line, reason)),
+                }
+                ControlFlow::Continue(())
+            });
+            errors
+        })
+        .collect()
+}
+
+/// Options to read rows for engine by, with rounding of engine taking over that of options.
+fn options_for(engine: &impl TransactionEngine, options: &ParseOptions) -> ParseOptions {
+    ParseOptions { rounding: engine.rounding().or(options.rounding), ..options.clone() }
+}
+
+/// Reads lines of reader as per options, skipping blank, comment (starting with `#`) and header lines, and hands every other line to on_row
+/// as 1 based line number, trimmed input and transaction with its timestamp or reason it could not be parsed.
+/// Reading stops as soon as on_row breaks.
+fn read_rows<R, F>(reader: R, options: &ParseOptions, stats: &mut ProcessingStats, mut on_row: F)
+where
+    R: BufRead,
+    F: FnMut(usize, &str, Result<(RawTransaction, Option<DateTime<Utc>>), String>) -> ControlFlow<()>,
+{
+    scan_rows(reader, options, stats, |line, input, options| match input {
+        Ok(input) => on_row(line, input, parse_row(input, options)),
+        Err(reason) => on_row(line, "", Err(reason)),
+    });
+}
+
+/// Transaction in trimmed input with its timestamp, or reason it could not be parsed.
+fn parse_row(input: &str, options: &ParseOptions) -> Result<(RawTransaction, Option<DateTime<Utc>>), String> {
+    RawTransaction::parse_with(input, options)
+        .and_then(|transaction| Ok((transaction, options.timestamp(input)?)))
+        .map_err(|err| err.to_string())
+}
+
+/// Same as read_rows, leaving parsing to on_row, which gets trimmed input, or reason line could not be read,
+/// along with options as updated by header. Line which is not valid utf-8 is handed over like any other unreadable line,
+/// while any other read error, e.g. of truncated gzip, is handed over once and ends reading, as reader would only fail again.
+fn scan_rows<R, F>(reader: R, options: &ParseOptions, stats: &mut ProcessingStats, mut on_row: F)
+where
+    R: BufRead,
+    F: FnMut(usize, Result<&str, String>, &ParseOptions) -> ControlFlow<()>,
+{
+    let mut options = options.clone();
+    let mut seen_row = false;
+    for (index, line) in reader.lines().enumerate() {
+        stats.lines_read += 1;
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                if on_row(index + 1, Err(err.to_string()), &options).is_break() || err.kind() != io::ErrorKind::InvalidData {
+                    return;
+                }
+                continue;
+            },
+        };
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            stats.lines_skipped += 1;
+            continue;
+        }
+        if !seen_row {
+            seen_row = true;
+            if let Some(columns) = ColumnMapping::from_header_with(trimmed, options.delimiter) {
+                options.columns = columns;
+                stats.lines_skipped += 1;
+                continue;
+            }
+            if trimmed.starts_with("type") {
+                stats.lines_skipped += 1;
+                continue;
+            }
+        }
+        if on_row(index + 1, Ok(trimmed), &options).is_break() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{amount::{Amount, RoundingMode}, InMemoryTransactionEngineBuilder};
+
+    #[test]
+    fn test_had_errors_only_for_bad_rows() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\n\nwithdrawal, 1, 2, 5.0\n";
+        let summary = run(input.as_bytes(), &mut InMemoryTransactionEngine::new());
+        assert_eq!(RunSummary { accepted: 1, rejected: 1, invalid: 0 }, summary);
+        assert!(!summary.had_errors());
+
+        let input = "deposit, 1, 1, 1.0\ndeposit, 1, abc, 1.0\n";
+        let summary = run(input.as_bytes(), &mut InMemoryTransactionEngine::new());
+        assert_eq!(RunSummary { accepted: 1, rejected: 0, invalid: 1 }, summary);
+        assert!(summary.had_errors());
+    }
+
+    #[test]
+    fn test_read_error_ends_input() {
+        // fails every read, like decoder of truncated gzip.
+        struct Failing;
+        impl io::Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::UnexpectedEof.into())
+            }
+        }
+        let summary = run(io::BufReader::new(Failing), &mut InMemoryTransactionEngine::new());
+        assert_eq!(RunSummary { accepted: 0, rejected: 0, invalid: 1 }, summary);
+
+        // line which is not utf-8 does not stop following ones.
+        let summary = run(&b"deposit, 1, 1, 1.0\ndeposit, 1, 2, \xff\ndeposit, 1, 3, 1.0\n"[..], &mut InMemoryTransactionEngine::new());
+        assert_eq!(RunSummary { accepted: 2, rejected: 0, invalid: 1 }, summary);
+    }
+
+    #[test]
+    fn test_report_json_has_all_sections() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\ndeposit, 1,\nwithdrawal, 1, 2, 5.0\ndispute, 1, 1\n";
+        let report = run_with_report(input.as_bytes(), &mut InMemoryTransactionEngine::new());
+        assert_eq!(RunSummary { accepted: 2, rejected: 1, invalid: 1 }, report.summary);
+        assert_eq!(vec![RowError { line: 3, input: "deposit, 1,".to_string(), reason: "too few fields".to_string() }],
+            report.errors);
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(2, json["summary"]["accepted"]);
+        assert_eq!(5, json["stats"]["lines_read"]);
+        assert_eq!(1, json["stats"]["applied_by_type"]["dispute"]);
+        assert_eq!(3, json["errors"][0]["line"]);
+    }
+
+    #[test]
+    fn test_process_multi_client_input() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\ndeposit, 2, 2, 2.0\ndeposit, 1, 3, 2.0\n\
+            withdrawal, 1, 4, 1.5\nnot a transaction\nwithdrawal, 2, 5, 3.0\ndispute, 2, 2\n";
+        let (engine, summary) = process(input.as_bytes());
+        assert_eq!(RunSummary { accepted: 5, rejected: 1, invalid: 1 }, summary);
+        assert_eq!("accepted: 5, rejected invalid: 1, rejected business: 1", summary.to_string());
+        let first = engine.get_client(1).unwrap();
+        assert_eq!((1, Amount::from_units(15_000), Amount::ZERO), (first.id(), first.available(), first.held()));
+        let second = engine.get_client(2).unwrap();
+        assert_eq!((Amount::ZERO, Amount::from_units(20_000), Amount::from_units(20_000)),
+            (second.available(), second.held(), second.total()));
+
+        let engine = InMemoryTransactionEngine::from_reader(input.as_bytes());
+        assert_eq!(2, engine.snap_shot_clients().len());
+    }
+
+    #[test]
+    fn test_columns_read_by_header_names() {
+        let input = "\nclient, tx, amount, type\n1, 1, 2.0, deposit\n1, 2, 0.5, withdrawal\n1, 1, , dispute\n";
+        let mut engine = InMemoryTransactionEngine::new();
+        let report = run_with_report(input.as_bytes(), &mut engine);
+        assert_eq!(RunSummary { accepted: 3, rejected: 0, invalid: 0 }, report.summary);
+        assert_eq!(2, report.stats.lines_skipped);
+        let client = engine.get_client(1).unwrap();
+        assert_eq!((Amount::from_units(-5_000), Amount::from_units(20_000), Amount::from_units(15_000)),
+            (client.available(), client.held(), client.total()));
+    }
+
+    #[test]
+    fn test_validate_file_reports_invalid_and_rejected_rows() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\nwithdrawal, 1, 2, 5.0\ndeposit, 1, 1, 2.0\n\
+            deposit, 1,\ndispute, 1, 1\nchargeback, 1, 1\ndeposit, 1, 3, 1.0\nresolve, 2, 9\n";
+        assert_eq!(vec![
+            (3, "insufficient funds".to_string()),
+            (4, "duplicate transaction id".to_string()),
+            (5, "too few fields".to_string()),
+            (8, "account locked".to_string()),
+            (9, "referred transaction not found".to_string()),
+        ], validate_file(input.as_bytes()));
+        assert!(validate_file("deposit, 1, 1, 1.0\n".as_bytes()).is_empty());
+    }
+
+    #[test]
+    fn test_files_processed_in_sequence() {
+        let first = "type, client, tx, amount\ndeposit, 1, 1, 2.0\n";
+        let second = "type, client, tx, amount\ndispute, 1, 1\nwithdrawal, 1, 2, 1.0\nbad row\n";
+        assert_eq!(vec![Vec::new(), vec![(3, "insufficient funds".to_string()), (4, "too few fields".to_string())]],
+            validate_files([first.as_bytes(), second.as_bytes()]));
+
+        let mut engine = InMemoryTransactionEngine::new();
+        let mut report = run_with_report(first.as_bytes(), &mut engine);
+        report.merge(run_with_report(second.as_bytes(), &mut engine));
+        assert_eq!(RunSummary { accepted: 2, rejected: 1, invalid: 1 }, report.summary);
+        assert_eq!((6, 2), (report.stats.lines_read, report.stats.lines_skipped));
+        assert_eq!(Some(&1), report.stats.applied_by_type.get("dispute"));
+        assert_eq!(vec![4], report.errors.iter().map(|error| error.line).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_comments_and_blank_lines_skipped() {
+        let plain = "type, client, tx, amount\ndeposit, 1, 1, 1.0\nwithdrawal, 1, 2, 0.5\n";
+        let commented = "# partner export\n\ntype, client, tx, amount\n   \n# first deposit\ndeposit, 1, 1, 1.0\n\
+            \t# indented comment\n\nwithdrawal, 1, 2, 0.5\n#\n";
+
+        let mut plain_engine = InMemoryTransactionEngine::new();
+        let plain_report = run_with_report(plain.as_bytes(), &mut plain_engine);
+        let mut commented_engine = InMemoryTransactionEngine::new();
+        let commented_report = run_with_report(commented.as_bytes(), &mut commented_engine);
+
+        assert_eq!(plain_report.summary, commented_report.summary);
+        assert!(commented_report.errors.is_empty());
+        assert_eq!(8, commented_report.stats.lines_skipped);
+        assert_eq!(plain_engine.get_client(1), commented_engine.get_client(1));
+    }
+
+    #[test]
+    fn test_timestamp_ordering() {
+        let header = "type, client, tx, amount, timestamp\n";
+        let in_order = "deposit, 1, 1, 5.0, 2024-01-01T00:00:00Z\nwithdrawal, 1, 2, 1.0, 2024-01-02T00:00:00Z\n";
+        let out_of_order = "withdrawal, 1, 3, 1.0, 2024-01-01T12:00:00Z\n";
+        let missing = "withdrawal, 1, 4, 1.0,\ndeposit, 2, 5, 1.0\n";
+        let input = format!("{}{}{}{}", header, in_order, out_of_order, missing);
+
+        let mut strict = InMemoryTransactionEngineBuilder::new().strict_ordering(true).build();
+        let summary = run(input.as_bytes(), &mut strict);
+        assert_eq!(RunSummary { accepted: 4, rejected: 1, invalid: 0 }, summary);
+        assert_eq!(Amount::from_units(30_000), strict.get_client(1).unwrap().available());
+        assert!(strict.metrics_text().contains("payment_engine_rejections_total{reason=\"out_of_order\"} 1\n"));
+
+        let mut lenient = InMemoryTransactionEngine::new();
+        let summary = run(input.as_bytes(), &mut lenient);
+        assert_eq!(RunSummary { accepted: 5, rejected: 0, invalid: 0 }, summary);
+        assert_eq!(Amount::from_units(20_000), lenient.get_client(1).unwrap().available());
+
+        let input = format!("{}deposit, 1, 1, 5.0, not a time\n", header);
+        let summary = run(input.as_bytes(), &mut InMemoryTransactionEngine::new());
+        assert_eq!(RunSummary { accepted: 0, rejected: 0, invalid: 1 }, summary);
+    }
+
+    #[test]
+    fn test_run_with_lenient_amounts() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, \"$1,234.56\"\nwithdrawal, 1, 2, $0.56\n";
+        let options = ParseOptions { lenient_amounts: true, ..ParseOptions::default() };
+        let mut engine = InMemoryTransactionEngine::new();
+        let report = run_with_options(input.as_bytes(), &mut engine, &options);
+        assert_eq!(RunSummary { accepted: 2, rejected: 0, invalid: 0 }, report.summary);
+        assert_eq!(Amount::from_units(12_340_000), engine.get_client(1).unwrap().available());
+
+        let report = run_with_report(input.as_bytes(), &mut InMemoryTransactionEngine::new());
+        assert_eq!(RunSummary { accepted: 0, rejected: 0, invalid: 2 }, report.summary);
+    }
+
+    #[test]
+    fn test_amounts_round_as_per_engine() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 0.50005\ndeposit, 1, 2, 0.00015\n";
+        let available = |mut engine: InMemoryTransactionEngine| {
+            let report = run_with_options(input.as_bytes(), &mut engine, &ParseOptions::default());
+            assert_eq!(RunSummary { accepted: 2, rejected: 0, invalid: 0 }, report.summary);
+            engine.get_client(1).unwrap().available()
+        };
+        assert_eq!(Amount::from_units(5_002), available(InMemoryTransactionEngine::new()));
+        assert_eq!(Amount::from_units(5_003), available(InMemoryTransactionEngineBuilder::new().with_rounding(RoundingMode::HalfUp).build()));
+        assert_eq!(Amount::from_units(5_001), available(InMemoryTransactionEngineBuilder::new().with_rounding(RoundingMode::Truncate).build()));
+
+        // engine rounding takes over that of options.
+        let options = ParseOptions { rounding: Some(RoundingMode::HalfUp), ..ParseOptions::default() };
+        let mut engine = InMemoryTransactionEngineBuilder::new().with_rounding(RoundingMode::Truncate).build();
+        run_strict(input.as_bytes(), &mut engine, &options).unwrap();
+        assert_eq!(Amount::from_units(5_001), engine.get_client(1).unwrap().available());
+    }
+
+    #[test]
+    fn test_tab_and_semicolon_separated_input() {
+        let csv = "type, client, tx, amount\ndeposit, 1, 1, 2.0\ndeposit, 2, 2, 1.0\ndispute, 1, 1,\nwithdrawal, 2, 3, 0.5\n";
+        let rows = |input: &str, delimiter| {
+            let options = ParseOptions { delimiter, ..ParseOptions::default() };
+            let mut engine = InMemoryTransactionEngine::new();
+            let report = run_with_options(input.as_bytes(), &mut engine, &options);
+            assert!(!report.summary.had_errors());
+            engine.snap_shot_clients_sorted().iter().map(|client| client.to_string()).collect::<Vec<_>>()
+        };
+        let expected = rows(csv, ',');
+        assert_eq!(2, expected.len());
+        assert_eq!(expected, rows(&csv.replace(", ", "\t").replace(',', "\t"), '\t'));
+        assert_eq!(expected, rows(&csv.replace(',', ";"), ';'));
+    }
+
+    #[test]
+    fn test_pipelined_run_matches_serial_run() {
+        let mut input = String::from("client, type, tx, amount\n# generated\n");
+        for transaction_id in 1..=5000u32 {
+            let client_id = transaction_id % 7;
+            match transaction_id % 5 {
+                0 => input.push_str(&format!("{}, withdrawal, {}, 3.0\n", client_id, transaction_id)),
+                1 => input.push_str(&format!("{}, dispute, {},\n", client_id, transaction_id - 1)),
+                2 => input.push_str("not a transaction\n"),
+                _ => input.push_str(&format!("{}, deposit, {}, 1.5\n", client_id, transaction_id)),
+            }
+        }
+
+        let mut serial = InMemoryTransactionEngine::new();
+        let serial_report = run_with_options(input.as_bytes(), &mut serial, &ParseOptions::default());
+        for parsers in [1, 3] {
+            let mut pipelined = InMemoryTransactionEngine::new();
+            let report = run_pipelined(input.as_bytes(), &mut pipelined, &ParseOptions::default(), parsers);
+            assert_eq!(serial_report, report);
+            assert_eq!(serial.snap_shot_clients_sorted(), pipelined.snap_shot_clients_sorted());
+        }
+        assert_eq!(1000, serial_report.summary.invalid);
+        assert_eq!(2, serial_report.stats.lines_skipped);
+    }
+
+    #[test]
+    fn test_strict_run_stops_at_first_invalid_row() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\nwithdrawal, 1, 2, 5.0\ndeposit, 1, x, 1.0\ndeposit, 1, 4, 1.0\n";
+        let mut engine = InMemoryTransactionEngine::new();
+        let invalid = run_strict(input.as_bytes(), &mut engine, &ParseOptions::default()).unwrap_err();
+        assert_eq!(RowError { line: 4, input: "deposit, 1, x, 1.0".to_string(), reason: "invalid transaction id".to_string() }, invalid);
+        // rows before invalid one are applied, rows after it are not.
+        assert_eq!(Amount::from_units(10_000), engine.get_client(1).unwrap().total());
+
+        let input = "deposit, 1, 1, 1.0\nwithdrawal, 1, 2, 5.0\n";
+        let report = run_strict(input.as_bytes(), &mut InMemoryTransactionEngine::new(), &ParseOptions::default()).unwrap();
+        assert_eq!(RunSummary { accepted: 1, rejected: 1, invalid: 0 }, report.summary);
+    }
+}
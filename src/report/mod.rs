@@ -0,0 +1,190 @@
+use std::{fmt::Display, io::{self, Write}};
+
+use crate::{account::Client, TransactionEngine};
+
+pub const HEADER: &str = "client,available,held,total,locked";
+
+/// HEADER with status column, see write_report_with_status.
+pub const STATUS_HEADER: &str = "client,available,held,total,locked,status";
+
+/// Header of write_totals, client id and total balance only.
+pub const TOTALS_HEADER: &str = "client,total";
+
+pub const TRANSACTION_HEADER: &str = "type,client,tx,amount";
+
+/// Line separating locked accounts from active ones in grouped output.
+pub const GROUP_SEPARATOR: &str = "--";
+
+/// Writes header followed by one line per client of engine, in order engine visits clients,
+/// which is ascending client id for InMemoryTransactionEngine.
+/// Lines are written while engine holds its clients, so w should not block for long.
+pub fn write_report<W: Write>(engine: &dyn TransactionEngine, w: &mut W) -> io::Result<()> {
+    writeln!(w, "{}", HEADER)?;
+    let mut result = Ok(());
+    engine.for_each_client(&mut |client| {
+        if result.is_ok() {
+            result = writeln!(w, "{}", client);
+        }
+    });
+    result
+}
+
+/// Same as write_report, with status of account, `active`, `frozen` or `overdrawn`, appended to every line.
+pub fn write_report_with_status<W: Write>(engine: &dyn TransactionEngine, w: &mut W) -> io::Result<()> {
+    writeln!(w, "{}", STATUS_HEADER)?;
+    let mut result = Ok(());
+    engine.for_each_client(&mut |client| {
+        if result.is_ok() {
+            result = writeln!(w, "{}, {}", client, client.status());
+        }
+    });
+    result
+}
+
+/// Same as write_report, with only client id and total of every client.
+pub fn write_totals<W: Write>(engine: &dyn TransactionEngine, w: &mut W) -> io::Result<()> {
+    writeln!(w, "{}", TOTALS_HEADER)?;
+    let mut result = Ok(());
+    engine.for_each_client(&mut |client| {
+        if result.is_ok() {
+            result = writeln!(w, "{}, {}", client.id(), client.total());
+        }
+    });
+    result
+}
+
+/// Writes every client of engine as json array, ordered by client id.
+pub fn write_json_report<W: Write>(engine: &dyn TransactionEngine, w: &mut W) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut *w, &engine.snap_shot_clients_sorted())?;
+    writeln!(w)
+}
+
+/// Writes header followed by locked accounts, GROUP_SEPARATOR and then active accounts.
+/// Accounts of both the groups are sorted by client id.
+pub fn write_grouped_by_locked<W: Write>(clients: &[Client], w: &mut W) -> io::Result<()> {
+    let mut sorted: Vec<&Client> = clients.iter().collect();
+    sorted.sort_by_key(|client| client.id());
+    let (locked, active): (Vec<&Client>, Vec<&Client>) = sorted.into_iter().partition(|client| client.is_locked());
+
+    writeln!(w, "{}", HEADER)?;
+    for client in locked {
+        writeln!(w, "{}", client)?;
+    }
+    writeln!(w, "{}", GROUP_SEPARATOR)?;
+    for client in active {
+        writeln!(w, "{}", client)?;
+    }
+    Ok(())
+}
+
+/// Writes transactions, RawTransaction of input or Transaction kept by engine, in same csv form as input,
+/// so output can be fed back to engine.
+pub fn write_transactions<'a, W, T, I>(transactions: I, w: &mut W) -> io::Result<()>
+where
+    W: Write,
+    T: Display + 'a,
+    I: IntoIterator<Item = &'a T>,
+{
+    writeln!(w, "{}", TRANSACTION_HEADER)?;
+    for transaction in transactions {
+        writeln!(w, "{}", transaction)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{transaction::RawTransaction, InMemoryTransactionEngine};
+
+    #[test]
+    fn test_write_report() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 2.5")).unwrap();
+
+        let mut output = Vec::new();
+        write_report(&engine, &mut output).unwrap();
+        assert_eq!("client,available,held,total,locked\n1, 2.5, 0, 2.5, false\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn test_write_json_report() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 0.1")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 2, 0.2")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 1, 1")).unwrap();
+
+        let mut output = Vec::new();
+        write_json_report(&engine, &mut output).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(serde_json::json!([{"client": 1, "available": "0.2", "held": "0.1", "total": "0.3", "locked": false}]), json);
+    }
+
+    #[test]
+    fn test_locked_accounts_come_first() {
+        let mut locked = Client::new(3);
+        locked.set_locked(true);
+        let clients = vec![Client::new(2), locked, Client::new(1)];
+
+        let mut output = Vec::new();
+        write_grouped_by_locked(&clients, &mut output).unwrap();
+        assert_eq!(
+            "client,available,held,total,locked\n3, 0, 0, 0, true\n--\n1, 0, 0, 0, false\n2, 0, 0, 0, false\n",
+            String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn test_write_report_with_status() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 1, 2.5")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 1, 1")).unwrap();
+        engine.add_transaction(RawTransaction::new("chargeback, 1, 1")).unwrap();
+
+        let mut output = Vec::new();
+        write_report_with_status(&engine, &mut output).unwrap();
+        assert_eq!("client,available,held,total,locked,status\n1, 0, 0, 0, true, frozen\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn test_rows_ordered_by_client_id() {
+        let engine = InMemoryTransactionEngine::new();
+        for client_id in [300, 2, 70, 1, 4000] {
+            engine.add_transaction(RawTransaction::new(&format!("deposit, {}, {}, 1.0", client_id, client_id))).unwrap();
+        }
+
+        let mut output = Vec::new();
+        write_report(&engine, &mut output).unwrap();
+        let client_ids: Vec<&str> = core::str::from_utf8(&output).unwrap().lines().skip(1)
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert_eq!(vec!["1", "2", "70", "300", "4000"], client_ids);
+
+        let client_ids: Vec<u64> = engine.snap_shot_clients_sorted().iter().map(Client::id).collect();
+        assert_eq!(vec![1, 2, 70, 300, 4000], client_ids);
+    }
+
+    #[test]
+    fn test_failed_withdrawals_leave_no_rows() {
+        let engine = InMemoryTransactionEngine::new();
+        for client_id in 1..=3 {
+            let _ = engine.add_transaction(RawTransaction::new(&format!("withdrawal, {}, {}, 1.0", client_id, client_id)));
+        }
+        assert!(engine.snap_shot_clients().is_empty());
+
+        let mut output = Vec::new();
+        write_report(&engine, &mut output).unwrap();
+        assert_eq!(format!("{}\n", HEADER), String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn test_write_totals() {
+        let engine = InMemoryTransactionEngine::new();
+        engine.add_transaction(RawTransaction::new("deposit, 2, 1, 2.5")).unwrap();
+        engine.add_transaction(RawTransaction::new("deposit, 1, 2, 1.0")).unwrap();
+        engine.add_transaction(RawTransaction::new("dispute, 2, 1")).unwrap();
+
+        let mut output = Vec::new();
+        write_totals(&engine, &mut output).unwrap();
+        assert_eq!("client,total\n1, 1\n2, 2.5\n", String::from_utf8(output).unwrap());
+    }
+}
@@ -0,0 +1,133 @@
+use std::{collections::{HashMap, HashSet, VecDeque}, io::{self, Read, Write}};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{account::Client, amount::Amount, transaction::{RawTransaction, Transaction, TransactionType}, InMemoryTransactionEngine,
+    TransactionOutcome};
+
+/// Everything engine holds, apart from config and observer. Written as json by save_snapshot,
+/// and kept in memory to roll back batch applied by add_transactions_atomic.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct EngineSnapshot {
+    clients: HashMap<u64, Client>,
+    transactions: HashMap<u32, Transaction>,
+    blocked_transactions: VecDeque<RawTransaction>,
+    finalized_transactions: Vec<Transaction>,
+    charged_back: HashSet<u32>,
+    #[serde(default)]
+    resolved_tx_ids: HashSet<u32>,
+    #[serde(default)]
+    lock_snapshots: HashMap<u64, Client>,
+    ledger_total: Amount,
+    deferred_transactions: Vec<RawTransaction>,
+    type_counts: HashMap<u64, HashMap<TransactionType, usize>>,
+    #[serde(default)]
+    rejection_counts: HashMap<TransactionOutcome, usize>,
+    last_timestamps: HashMap<u64, DateTime<Utc>>,
+    #[serde(default)]
+    held_fees: HashMap<u64, Amount>,
+}
+
+impl InMemoryTransactionEngine {
+    /// Writes state of engine, including locked accounts and disputes in flight, so that
+    /// engine loaded back by load_snapshot carries on from exactly here.
+    pub fn save_snapshot<W: Write>(&self, w: W) -> io::Result<()> {
+        serde_json::to_writer(w, &self.take_snapshot())?;
+        Ok(())
+    }
+
+    /// Engine with default config and state written by save_snapshot.
+    pub fn load_snapshot<R: Read>(r: R) -> io::Result<InMemoryTransactionEngine> {
+        let snapshot: EngineSnapshot = serde_json::from_reader(r)?;
+        let mut engine = InMemoryTransactionEngine::new();
+        engine.restore_snapshot(snapshot);
+        Ok(engine)
+    }
+
+    pub(crate) fn take_snapshot(&self) -> EngineSnapshot {
+        let state = self.state.lock().unwrap();
+        let clients = self.clients.lock().unwrap();
+        EngineSnapshot {
+            clients: clients.clone(),
+            transactions: state.tranasctions.clone(),
+            blocked_transactions: state.blocked_transactions.values().cloned().collect(),
+            finalized_transactions: state.finalized_transactions.clone(),
+            charged_back: state.charged_back.clone(),
+            resolved_tx_ids: state.resolved_tx_ids.clone(),
+            lock_snapshots: state.lock_snapshots.clone(),
+            ledger_total: state.ledger_total,
+            deferred_transactions: state.deferred_transactions.clone(),
+            type_counts: state.type_counts.clone(),
+            rejection_counts: state.rejection_counts.clone(),
+            last_timestamps: state.last_timestamps.clone(),
+            held_fees: state.held_fees.clone(),
+        }
+    }
+
+    /// Replaces everything engine holds with snapshot.
+    pub(crate) fn restore_snapshot(&mut self, snapshot: EngineSnapshot) {
+        let state = self.state.get_mut().unwrap();
+        state.tranasctions = snapshot.transactions;
+        *self.clients.get_mut().unwrap() = snapshot.clients;
+        state.blocked_transactions = snapshot.blocked_transactions.into_iter().enumerate()
+            .map(|(sequence, blocked)| (sequence as u64, blocked))
+            .collect();
+        state.next_blocked_sequence = state.blocked_transactions.len() as u64;
+        state.finalized_transactions = snapshot.finalized_transactions;
+        state.charged_back = snapshot.charged_back;
+        state.resolved_tx_ids = snapshot.resolved_tx_ids;
+        state.lock_snapshots = snapshot.lock_snapshots;
+        state.ledger_total = snapshot.ledger_total;
+        state.deferred_transactions = snapshot.deferred_transactions;
+        state.type_counts = snapshot.type_counts;
+        state.rejection_counts = snapshot.rejection_counts;
+        state.last_timestamps = snapshot.last_timestamps;
+        state.held_fees = snapshot.held_fees;
+        self.rebuild_client_index();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{error::TransactionError, TransactionEngine};
+
+    fn sorted_rows(engine: &InMemoryTransactionEngine) -> Vec<String> {
+        let mut rows: Vec<String> = engine.snap_shot_clients().iter().map(|client| client.to_string()).collect();
+        rows.sort();
+        rows
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let engine = InMemoryTransactionEngine::new();
+        for input in ["deposit, 1, 1, 1.5", "deposit, 1, 2, 0.25", "dispute, 1, 2", "deposit, 2, 3, 3.0",
+            "withdrawal, 2, 4, 1.0", "dispute, 2, 3", "chargeback, 2, 3", "deposit, 2, 5, 1.0"] {
+            let _ = engine.add_transaction(RawTransaction::new(input));
+        }
+
+        let mut saved = Vec::new();
+        engine.save_snapshot(&mut saved).unwrap();
+        let loaded = InMemoryTransactionEngine::load_snapshot(saved.as_slice()).unwrap();
+
+        assert_eq!(sorted_rows(&engine), sorted_rows(&loaded));
+        assert_eq!(engine.get_client(2), loaded.get_client(2));
+        assert_eq!(engine.stats(), loaded.stats());
+        assert_eq!(engine.client_type_counts(1), loaded.client_type_counts(1));
+        assert!(loaded.lock_snapshot(2).is_some());
+        assert_eq!(engine.lock_snapshot(2), loaded.lock_snapshot(2));
+        assert_eq!(Ok(()), loaded.conservation_check());
+
+        // dispute in flight before save can still be resolved.
+        assert_eq!(Ok(()), loaded.add_transaction(RawTransaction::new("resolve, 1, 2")));
+        let client = loaded.get_client(1).unwrap();
+        assert_eq!((Amount::from_units(17_500), Amount::ZERO), (client.available(), client.held()));
+        assert_eq!(Err(TransactionError::AccountLocked), loaded.add_transaction(RawTransaction::new("deposit, 2, 6, 1.0")));
+    }
+
+    #[test]
+    fn test_load_invalid_snapshot_fails() {
+        assert!(InMemoryTransactionEngine::load_snapshot("not json".as_bytes()).is_err());
+    }
+}
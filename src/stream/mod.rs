@@ -0,0 +1,49 @@
+use std::pin::pin;
+
+use futures::{Stream, StreamExt};
+
+use crate::{transaction::RawTransaction, InMemoryTransactionEngine, TransactionEngine};
+
+/// Transactions applied before giving other tasks a turn, as applying a transaction never awaits.
+const YIELD_EVERY: usize = 1024;
+
+/// Applies every transaction of stream, in order, to engine with default config and returns engine once stream ends.
+/// Next transaction is only pulled once previous one is applied, so a slow engine holds back source
+/// instead of transactions piling up in memory.
+pub async fn process_stream<S: Stream<Item = RawTransaction>>(stream: S) -> InMemoryTransactionEngine {
+    let engine = InMemoryTransactionEngine::new();
+    let mut stream = pin!(stream);
+    let mut applied = 0;
+    while let Some(transaction) = stream.next().await {
+        let _ = engine.add_transaction(transaction);
+        applied += 1;
+        if applied % YIELD_EVERY == 0 {
+            tokio::task::yield_now().await;
+        }
+    }
+    engine
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_process_stream() {
+        let transactions = ["deposit, 1, 1, 2.0", "deposit, 2, 2, 1.0", "withdrawal, 1, 3, 0.5", "dispute, 2, 2"]
+            .into_iter()
+            .map(RawTransaction::new);
+        let engine = process_stream(futures::stream::iter(transactions)).await;
+        assert_eq!("1, 1.5, 0, 1.5, false", engine.get_client(1).unwrap().to_string());
+        assert_eq!("2, 0, 1, 1, false", engine.get_client(2).unwrap().to_string());
+    }
+
+    #[tokio::test]
+    async fn test_long_stream_is_applied_in_order() {
+        let transactions = (1..=3 * YIELD_EVERY as u32)
+            .map(|transaction_id| RawTransaction::new(&format!("deposit, 1, {}, 1.0", transaction_id)))
+            .chain([RawTransaction::new("withdrawal, 1, 0, 3072.0")]);
+        let engine = process_stream(futures::stream::iter(transactions)).await;
+        assert_eq!("1, 0, 0, 0, false", engine.get_client(1).unwrap().to_string());
+    }
+}
@@ -1,18 +1,28 @@
+pub mod options;
+#[cfg(feature = "std")]
+pub mod reader;
 pub mod validator;
 
-use std::slice::Iter;
+use core::{fmt::Display, slice::Iter, str::FromStr};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use self::validator::is_valid_input;
+use crate::amount::Amount;
 
+use self::{options::ParseOptions, validator::validate_input_with};
 
+pub use self::validator::ParseTransactionError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum TransactionType {
     Deposite,
     Withdrawal,
     Dispute,
     Reslove,
-    Chargeback
+    Chargeback,
+    Pending,
+    Capture,
+    ChargebackReversal,
 }
 
 impl TransactionType {
@@ -23,92 +33,249 @@ impl TransactionType {
             Withdrawal => "withdrawal",
             Dispute => "dispute",
             Reslove => "resolve",
-            Chargeback => "chargeback"
+            Chargeback => "chargeback",
+            Pending => "pending",
+            Capture => "capture",
+            ChargebackReversal => "chargeback_reversal",
         }
     }
 
     pub fn iterator() -> Iter<'static, TransactionType> {
         use TransactionType::*;
-        static TRANSACTION_TYPES: [TransactionType; 5] = [Deposite, Withdrawal, Dispute, Reslove, Chargeback];
+        static TRANSACTION_TYPES: [TransactionType; 8] = [Deposite, Withdrawal, Dispute, Reslove, Chargeback, Pending, Capture,
+            ChargebackReversal];
         TRANSACTION_TYPES.iter()
     }
+
+    /// true for types whose rows have to carry an amount.
+    pub fn requires_amount(&self) -> bool {
+        matches!(self, TransactionType::Deposite | TransactionType::Withdrawal | TransactionType::Pending)
+    }
 }
 
-#[derive(Debug, Deserialize)]
-pub enum Transaction {
-    Deposit {client_id: u16, transaction_id: u32, amount: f64},
-    Withdrawal {client_id: u16, transaction_id: u32, amount: f64},
-    DisputedDeposit {client_id: u16, transaction_id: u32, amount: f64},
-    DisputedWithdrawal {client_id: u16, transaction_id: u32, amount: f64},
-    Dispute {client_id: u16, transaction_id: u32},
-    Reslove {client_id: u16, transaction_id: u32},
-    Chargeback {client_id: u16, transaction_id: u32},
+impl FromStr for TransactionType {
+    type Err = ParseTransactionError;
+
+    /// Reverse of as_str, which is the only place type names are spelled out.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        TransactionType::iterator()
+            .find(|transaction_type| transaction_type.as_str() == input)
+            .copied()
+            .ok_or(ParseTransactionError::UnknownTransactionType)
+    }
 }
 
-impl Transaction {
-    /// This assumes input is valid str that can be converted to Transaction using is_valid_input.
+/// Transaction as it comes in input. Engine keeps deposits, withdrawals and pending deposits it applied as
+/// Transaction, which is where their disputed states live, so input can never hold a disputed transaction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RawTransaction {
+    Deposit {client_id: u64, transaction_id: u32, amount: Amount},
+    Withdrawal {client_id: u64, transaction_id: u32, amount: Amount},
+    /// amount is part of referred transaction being disputed, None disputes all of it.
+    Dispute {client_id: u64, transaction_id: u32, #[serde(default)] amount: Option<Amount>},
+    Reslove {client_id: u64, transaction_id: u32},
+    Chargeback {client_id: u64, transaction_id: u32},
+    /// deposit authorized but not yet captured, its amount stays held till Capture arrives.
+    PendingDeposit {client_id: u64, transaction_id: u32, amount: Amount},
+    Capture {client_id: u64, transaction_id: u32},
+    /// undoes chargeback applied in error, unlocking account.
+    ChargebackReversal {client_id: u64, transaction_id: u32},
+}
+
+impl RawTransaction {
+    /// This assumes input is valid str that can be converted to RawTransaction using is_valid_input.
+    /// It will panic otherwise. Use parse for input that is not known to be valid.
+    pub fn new(input: &str) -> RawTransaction {
+        RawTransaction::new_with(input, &ParseOptions::default())
+    }
+
+    /// Same as new, but reads fields as per options.
+    /// This assumes input is valid str that can be converted to RawTransaction using is_valid_input_with.
     /// It will panic otherwise.
-    pub fn new(input: &str) -> Transaction {
-        use Transaction::*;
-        assert!(is_valid_input(input));
+    pub fn new_with(input: &str, options: &ParseOptions) -> RawTransaction {
+        RawTransaction::parse_with(input, options)
+            .unwrap_or_else(|err| panic!("Invalid input {} as {}", input, err))
+    }
 
-        let splitted: Vec<&str> = input.split(&[',', ' ']).filter(|each| !each.is_empty()).collect();
-        let trans_type = *splitted.get(0).unwrap();
-        let client_id = splitted.get(1).unwrap().parse::<u16>().unwrap();
+    /// Converts input to RawTransaction, telling why it could not be converted otherwise.
+    pub fn parse(input: &str) -> Result<RawTransaction, ParseTransactionError> {
+        RawTransaction::parse_with(input, &ParseOptions::default())
+    }
+
+    /// Same as parse, but reads fields as per options.
+    pub fn parse_with(input: &str, options: &ParseOptions) -> Result<RawTransaction, ParseTransactionError> {
+        validate_input_with(input, options)?;
+        Ok(RawTransaction::from_valid_fields(&options.fields(input), options))
+    }
+
+    /// Builds transaction of given type. Amount, greater than zero, is required for deposit, withdrawal and pending,
+    /// is optional for dispute, and is ignored for others.
+    pub fn from_parts(transaction_type: TransactionType, client_id: u64, transaction_id: u32, amount: Option<Amount>)
+        -> Result<RawTransaction, ParseTransactionError> {
+        use RawTransaction::*;
+        let has_amount = amount.is_some();
+        let amount = || match amount {
+            None => Err(ParseTransactionError::MissingAmount),
+            Some(amount) if !amount.is_positive() => Err(ParseTransactionError::InvalidAmount),
+            Some(amount) => Ok(amount),
+        };
+        Ok(match transaction_type {
+            TransactionType::Deposite => Deposit { client_id, transaction_id, amount: amount()? },
+            TransactionType::Withdrawal => Withdrawal { client_id, transaction_id, amount: amount()? },
+            TransactionType::Pending => PendingDeposit { client_id, transaction_id, amount: amount()? },
+            TransactionType::Dispute => Dispute { client_id, transaction_id, amount: has_amount.then(amount).transpose()? },
+            TransactionType::Reslove => Reslove { client_id, transaction_id },
+            TransactionType::Chargeback => Chargeback { client_id, transaction_id },
+            TransactionType::Capture => Capture { client_id, transaction_id },
+            TransactionType::ChargebackReversal => ChargebackReversal { client_id, transaction_id },
+        })
+    }
+
+    /// fields should be validated, in `type, client, tx, amount` order.
+    fn from_valid_fields(splitted: &[&str], options: &ParseOptions) -> RawTransaction {
+        let trans_type = splitted.first().unwrap().parse::<TransactionType>().unwrap();
+        let client_id = splitted.get(1).unwrap().parse::<u64>().unwrap();
         let transaction_id = splitted.get(2).unwrap().parse::<u32>().unwrap();
-        let amount = splitted.get(3).map(|amount| amount.parse::<f64>().unwrap());
-        if trans_type.eq("deposit") {
-            Deposit {
-                client_id,
-                transaction_id,
-                amount: amount.unwrap(),
-            }
-        } else if trans_type.eq("withdrawal") {
-            Withdrawal {
-                client_id,
-                transaction_id,
-                amount: amount.unwrap(),
-            }
-        } else if trans_type.eq("dispute") {
-            Dispute {
-                client_id,
-                transaction_id,
-            }
-        } else if trans_type.eq("resolve") {
-            Reslove {
-                client_id,
-                transaction_id,
-            }
-        } else if trans_type.eq("chargeback") {
-            Chargeback {
-                client_id,
-                transaction_id,
-            }
-        } else {
-            eprint!("Invalie input {}", input);
-            panic!("This should not happen as code has already validated input")
+        let amount = splitted.get(3).and_then(|amount| options.amount(amount));
+        RawTransaction::from_parts(trans_type, client_id, transaction_id, amount)
+            .unwrap_or_else(|err| unreachable!("{:?} has already been validated, still {}", splitted, err))
+    }
+
+    /// Transaction engine keeps for deposit, withdrawal and pending deposit, which are ones later transactions refer to.
+    /// None for referring transactions.
+    pub fn to_stored(&self) -> Option<Transaction> {
+        match *self {
+            RawTransaction::Deposit { client_id, transaction_id, amount } => Some(Transaction::Deposit { client_id, transaction_id, amount }),
+            RawTransaction::Withdrawal { client_id, transaction_id, amount } => Some(Transaction::Withdrawal { client_id, transaction_id, amount }),
+            RawTransaction::PendingDeposit { client_id, transaction_id, amount } =>
+                Some(Transaction::PendingDeposit { client_id, transaction_id, amount }),
+            _ => None,
+        }
+    }
+
+    pub fn is_non_refering(&self) -> bool {
+        matches!(self, RawTransaction::Deposit { client_id: _, transaction_id: _, amount: _ }
+                | RawTransaction::Withdrawal { client_id: _, transaction_id: _, amount: _ }
+                | RawTransaction::PendingDeposit { client_id: _, transaction_id: _, amount: _ })
+    }
+
+    pub fn transaction_type(&self) -> TransactionType {
+        match self {
+            RawTransaction::Deposit { client_id: _, transaction_id: _, amount: _ } => TransactionType::Deposite,
+            RawTransaction::Withdrawal { client_id: _, transaction_id: _, amount: _ } => TransactionType::Withdrawal,
+            RawTransaction::PendingDeposit { client_id: _, transaction_id: _, amount: _ } => TransactionType::Pending,
+            RawTransaction::Dispute { client_id: _, transaction_id: _, amount: _ } => TransactionType::Dispute,
+            RawTransaction::Reslove { client_id: _, transaction_id: _ } => TransactionType::Reslove,
+            RawTransaction::Chargeback { client_id: _, transaction_id: _ } => TransactionType::Chargeback,
+            RawTransaction::Capture { client_id: _, transaction_id: _ } => TransactionType::Capture,
+            RawTransaction::ChargebackReversal { client_id: _, transaction_id: _ } => TransactionType::ChargebackReversal,
+        }
+    }
+
+    pub fn client_id(&self) -> u64 {
+        match self {
+            RawTransaction::Deposit { client_id, transaction_id: _, amount: _ }
+            | RawTransaction::Withdrawal { client_id, transaction_id: _, amount: _ }
+            | RawTransaction::PendingDeposit { client_id, transaction_id: _, amount: _ } => *client_id,
+            RawTransaction::Dispute { client_id, transaction_id: _, amount: _ }
+            | RawTransaction::Reslove { client_id, transaction_id: _ }
+            | RawTransaction::Chargeback { client_id, transaction_id: _ }
+            | RawTransaction::Capture { client_id, transaction_id: _ }
+            | RawTransaction::ChargebackReversal { client_id, transaction_id: _ } => *client_id,
         }
     }
 
-    /// this should only be called for non_refering transcation.
-    pub fn make_disputed_transaction(self) -> Result<(Transaction, f64), Transaction>{
+    pub fn transaction_id(&self) -> u32 {
         match self {
-            Transaction::Deposit { client_id, transaction_id, amount} => Ok((
-                Transaction::DisputedDeposit { client_id, transaction_id, amount}, amount)),
-            Transaction::Withdrawal { client_id, transaction_id, amount } => Ok((
-                Transaction::DisputedWithdrawal { client_id, transaction_id, amount }, amount)),
+            RawTransaction::Deposit { client_id: _, transaction_id, amount: _ }
+            | RawTransaction::Withdrawal { client_id: _, transaction_id, amount: _ }
+            | RawTransaction::PendingDeposit { client_id: _, transaction_id, amount: _ } => *transaction_id,
+            RawTransaction::Dispute { client_id: _, transaction_id, amount: _ }
+            | RawTransaction::Reslove { client_id: _, transaction_id }
+            | RawTransaction::Chargeback { client_id: _, transaction_id }
+            | RawTransaction::Capture { client_id: _, transaction_id }
+            | RawTransaction::ChargebackReversal { client_id: _, transaction_id } => *transaction_id,
+        }
+    }
+}
+
+impl Display for RawTransaction {
+    /// Writes transaction in same csv form it is read, i.e. `type,client,tx,amount`.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use RawTransaction::*;
+        match self {
+            Deposit { client_id, transaction_id, amount } => write!(f, "{},{},{},{}",
+                TransactionType::Deposite.as_str(), client_id, transaction_id, amount),
+            Withdrawal { client_id, transaction_id, amount } => write!(f, "{},{},{},{}",
+                TransactionType::Withdrawal.as_str(), client_id, transaction_id, amount),
+            PendingDeposit { client_id, transaction_id, amount } => write!(f, "{},{},{},{}",
+                TransactionType::Pending.as_str(), client_id, transaction_id, amount),
+            Dispute { client_id, transaction_id, amount: None } => write!(f, "{},{},{},",
+                TransactionType::Dispute.as_str(), client_id, transaction_id),
+            Dispute { client_id, transaction_id, amount: Some(amount) } => write!(f, "{},{},{},{}",
+                TransactionType::Dispute.as_str(), client_id, transaction_id, amount),
+            Reslove { client_id, transaction_id } => write!(f, "{},{},{},",
+                TransactionType::Reslove.as_str(), client_id, transaction_id),
+            Chargeback { client_id, transaction_id } => write!(f, "{},{},{},",
+                TransactionType::Chargeback.as_str(), client_id, transaction_id),
+            Capture { client_id, transaction_id } => write!(f, "{},{},{},",
+                TransactionType::Capture.as_str(), client_id, transaction_id),
+            ChargebackReversal { client_id, transaction_id } => write!(f, "{},{},{},",
+                TransactionType::ChargebackReversal.as_str(), client_id, transaction_id),
+        }
+    }
+}
+
+impl TryFrom<&str> for RawTransaction {
+    type Error = ParseTransactionError;
+
+    /// Same as parse, with default options.
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        RawTransaction::parse(input)
+    }
+}
+
+/// Transaction engine keeps, for later transactions to refer to. It is a deposit, withdrawal or pending deposit
+/// of input, or one of them under dispute.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Transaction {
+    Deposit {client_id: u64, transaction_id: u32, amount: Amount},
+    Withdrawal {client_id: u64, transaction_id: u32, amount: Amount},
+    /// amount is disputed part of deposit, undisputed is what is left of it, which is zero for dispute of whole deposit.
+    DisputedDeposit {client_id: u64, transaction_id: u32, amount: Amount, #[serde(default)] undisputed: Amount},
+    /// amount is disputed part of withdrawal, undisputed is what is left of it, which is zero for dispute of whole withdrawal.
+    DisputedWithdrawal {client_id: u64, transaction_id: u32, amount: Amount, #[serde(default)] undisputed: Amount},
+    /// deposit authorized but not yet captured, its amount stays held till Capture arrives.
+    PendingDeposit {client_id: u64, transaction_id: u32, amount: Amount},
+}
+
+impl Transaction {
+    /// this should only be called for undisputed Deposit or Withdrawal.
+    /// portion is part of amount disputed, None disputes all of it. Portion more than amount can not be disputed.
+    /// Returns disputed transaction along with disputed amount.
+    pub fn make_disputed_transaction(self, portion: Option<Amount>) -> Result<(Transaction, Amount), Transaction>{
+        match self {
+            Transaction::Deposit { client_id, transaction_id, amount} if portion.is_none_or(|portion| portion <= amount) => {
+                let disputed = portion.unwrap_or(amount);
+                Ok((Transaction::DisputedDeposit { client_id, transaction_id, amount: disputed, undisputed: amount - disputed }, disputed))
+            },
+            Transaction::Withdrawal { client_id, transaction_id, amount } if portion.is_none_or(|portion| portion <= amount) => {
+                let disputed = portion.unwrap_or(amount);
+                Ok((Transaction::DisputedWithdrawal { client_id, transaction_id, amount: disputed, undisputed: amount - disputed }, disputed))
+            },
             _ => Err(self),
         }
     }
 
-    pub fn get_disputed_transaction(self) -> Result<(Transaction, f64), Transaction> {
+    /// Disputed part of transaction as it was before dispute, along with disputed amount.
+    pub fn get_disputed_transaction(self) -> Result<(Transaction, Amount), Transaction> {
         match self {
-            Transaction::DisputedDeposit { client_id, transaction_id, amount } => Ok((Transaction::Deposit {
+            Transaction::DisputedDeposit { client_id, transaction_id, amount, undisputed: _ } => Ok((Transaction::Deposit {
                 client_id,
                 transaction_id,
                 amount,
             }, amount)),
-            Transaction::DisputedWithdrawal { client_id, transaction_id, amount } => Ok((Transaction::Withdrawal {
+            Transaction::DisputedWithdrawal { client_id, transaction_id, amount, undisputed: _ } => Ok((Transaction::Withdrawal {
                 client_id,
                 transaction_id,
                 amount,
@@ -117,31 +284,150 @@ impl Transaction {
         }
     }
 
-    pub fn is_disputed(&self) -> bool {
+    /// Part of partially disputed transaction left out of dispute, as it was before dispute.
+    /// This stays disputable once dispute is settled. None if whole transaction is disputed, or it is not disputed.
+    pub fn undisputed_remainder(&self) -> Option<Transaction> {
+        match *self {
+            Transaction::DisputedDeposit { client_id, transaction_id, amount: _, undisputed } if undisputed.is_positive() =>
+                Some(Transaction::Deposit { client_id, transaction_id, amount: undisputed }),
+            Transaction::DisputedWithdrawal { client_id, transaction_id, amount: _, undisputed } if undisputed.is_positive() =>
+                Some(Transaction::Withdrawal { client_id, transaction_id, amount: undisputed }),
+            _ => None,
+        }
+    }
+
+    /// this should only be called for PendingDeposit, which once captured becomes regular Deposit.
+    pub fn make_captured_transaction(self) -> Result<(Transaction, Amount), Transaction> {
         match self {
-            Transaction::DisputedDeposit { client_id: _, transaction_id: _, amount: _ }
-                | Transaction::DisputedWithdrawal { client_id: _, transaction_id: _, amount: _ } => true,
-            _ => false,
+            Transaction::PendingDeposit { client_id, transaction_id, amount } => Ok((Transaction::Deposit {
+                client_id,
+                transaction_id,
+                amount,
+            }, amount)),
+            _ => Err(self),
         }
     }
 
-    pub fn is_non_refering(&self) -> bool {
+    pub fn is_disputed(&self) -> bool {
+        matches!(self, Transaction::DisputedDeposit { client_id: _, transaction_id: _, amount: _, undisputed: _ }
+                | Transaction::DisputedWithdrawal { client_id: _, transaction_id: _, amount: _, undisputed: _ })
+    }
+
+    /// Type of input transaction this came from, disputed transactions report type they were before dispute.
+    pub fn transaction_type(&self) -> TransactionType {
         match self {
             Transaction::Deposit { client_id: _, transaction_id: _, amount: _ }
-                | Transaction::Withdrawal { client_id: _, transaction_id: _, amount: _ } => true,
-            _ => false
+            | Transaction::DisputedDeposit { client_id: _, transaction_id: _, amount: _, undisputed: _ } => TransactionType::Deposite,
+            Transaction::Withdrawal { client_id: _, transaction_id: _, amount: _ }
+            | Transaction::DisputedWithdrawal { client_id: _, transaction_id: _, amount: _, undisputed: _ } => TransactionType::Withdrawal,
+            Transaction::PendingDeposit { client_id: _, transaction_id: _, amount: _ } => TransactionType::Pending,
         }
     }
 
-    pub fn client_id(&self) -> u16 {
+    pub fn client_id(&self) -> u64 {
         match self {
             Transaction::Deposit { client_id, transaction_id: _, amount: _ }
             | Transaction::Withdrawal { client_id, transaction_id: _, amount: _ }
-            | Transaction::DisputedWithdrawal { client_id, transaction_id: _, amount: _ }
-            | Transaction::DisputedDeposit { client_id, transaction_id: _, amount: _ } => *client_id,
-            Transaction::Dispute { client_id, transaction_id: _ }
-            | Transaction::Reslove { client_id, transaction_id: _ }
-            | Transaction::Chargeback { client_id, transaction_id: _ } => *client_id,
+            | Transaction::DisputedWithdrawal { client_id, transaction_id: _, amount: _, undisputed: _ }
+            | Transaction::DisputedDeposit { client_id, transaction_id: _, amount: _, undisputed: _ }
+            | Transaction::PendingDeposit { client_id, transaction_id: _, amount: _ } => *client_id,
+        }
+    }
+
+    pub fn transaction_id(&self) -> u32 {
+        match self {
+            Transaction::Deposit { client_id: _, transaction_id, amount: _ }
+            | Transaction::Withdrawal { client_id: _, transaction_id, amount: _ }
+            | Transaction::DisputedWithdrawal { client_id: _, transaction_id, amount: _, undisputed: _ }
+            | Transaction::DisputedDeposit { client_id: _, transaction_id, amount: _, undisputed: _ }
+            | Transaction::PendingDeposit { client_id: _, transaction_id, amount: _ } => *transaction_id,
+        }
+    }
+}
+
+impl Display for Transaction {
+    /// Writes transaction in csv form of input it came from, i.e. `type,client,tx,amount`.
+    /// Disputed transactions are written as transaction they were before dispute.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use Transaction::*;
+        match self {
+            Deposit { client_id, transaction_id, amount } => write!(f, "{},{},{},{}",
+                TransactionType::Deposite.as_str(), client_id, transaction_id, amount),
+            DisputedDeposit { client_id, transaction_id, amount, undisputed } => write!(f, "{},{},{},{}",
+                TransactionType::Deposite.as_str(), client_id, transaction_id, *amount + *undisputed),
+            Withdrawal { client_id, transaction_id, amount } => write!(f, "{},{},{},{}",
+                TransactionType::Withdrawal.as_str(), client_id, transaction_id, amount),
+            DisputedWithdrawal { client_id, transaction_id, amount, undisputed } => write!(f, "{},{},{},{}",
+                TransactionType::Withdrawal.as_str(), client_id, transaction_id, *amount + *undisputed),
+            PendingDeposit { client_id, transaction_id, amount } => write!(f, "{},{},{},{}",
+                TransactionType::Pending.as_str(), client_id, transaction_id, amount),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_disputed_transaction_has_undisputed_form() {
+        let amount = Amount::from_units(10_000);
+        let transactions = [
+            Transaction::Deposit { client_id: 1, transaction_id: 1, amount },
+            Transaction::Withdrawal { client_id: 1, transaction_id: 2, amount },
+            Transaction::PendingDeposit { client_id: 1, transaction_id: 3, amount },
+            Transaction::DisputedDeposit { client_id: 1, transaction_id: 1, amount, undisputed: Amount::ZERO },
+            Transaction::DisputedWithdrawal { client_id: 1, transaction_id: 2, amount, undisputed: amount },
+        ];
+        // engine relies on is_disputed to tell when get_disputed_transaction succeeds.
+        for transaction in transactions {
+            assert_eq!(transaction.is_disputed(), transaction.clone().get_disputed_transaction().is_ok(), "{:?}", transaction);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_dispute_with_amount() {
+        assert_eq!("dispute,1,1,30", RawTransaction::new("dispute, 1, 1, 30.0").to_string());
+        assert_eq!("dispute,1,1,", RawTransaction::new("dispute, 1, 1,").to_string());
+        assert_eq!(Some(ParseTransactionError::InvalidAmount), RawTransaction::parse("dispute, 1, 1, -30.0").err());
+        // amount is still ignored for other referring transactions.
+        assert_eq!("resolve,1,1,", RawTransaction::new("resolve, 1, 1, 30.0").to_string());
+
+        let deposit = RawTransaction::new("deposit, 1, 1, 100.0").to_stored().unwrap();
+        let (disputed, amount) = deposit.make_disputed_transaction(Some("30".parse().unwrap())).unwrap();
+        assert_eq!(Amount::from_units(300_000), amount);
+        assert_eq!("deposit,1,1,100", disputed.to_string());
+        assert_eq!("deposit,1,1,70", disputed.undisputed_remainder().unwrap().to_string());
+        assert_eq!("deposit,1,1,30", disputed.get_disputed_transaction().unwrap().0.to_string());
+    }
+
+    #[test]
+    fn test_input_is_never_disputed() {
+        // RawTransaction has no disputed variant, so what engine stores for input starts undisputed.
+        for transaction_type in TransactionType::iterator() {
+            let transaction = RawTransaction::new(&format!("{}, 1, 1, 1.0", transaction_type.as_str()));
+            assert_eq!(*transaction_type, transaction.transaction_type());
+            match transaction.to_stored() {
+                Some(stored) => {
+                    assert!(!stored.is_disputed(), "{:?}", stored);
+                    assert_eq!(*transaction_type, stored.transaction_type());
+                    assert_eq!(transaction.to_string(), stored.to_string());
+                },
+                None => assert!(!transaction.is_non_refering(), "{:?}", transaction),
+            }
+        }
+    }
+
+    #[test]
+    fn test_try_from_str() {
+        assert_eq!("deposit,1,1,5", RawTransaction::try_from("deposit, 1, 1, 5.0").unwrap().to_string());
+        assert_eq!(Some(ParseTransactionError::InvalidClientId), RawTransaction::try_from("deposit, x, 1, 5.0").err());
+        assert_eq!(RawTransaction::parse("withdrawal, 1, 2").err(), RawTransaction::try_from("withdrawal, 1, 2").err());
+
+        fn client_ids(rows: &[&str]) -> Result<Vec<u64>, ParseTransactionError> {
+            rows.iter().map(|row| Ok(RawTransaction::try_from(*row)?.client_id())).collect()
+        }
+        assert_eq!(Ok(vec![1, 2]), client_ids(&["deposit, 1, 1, 5.0", "dispute, 2, 1"]));
+        assert!(client_ids(&["deposit, 1, 1, 5.0", "deposit, 2"]).is_err());
+    }
+}
@@ -0,0 +1,303 @@
+use alloc::{string::{String, ToString}, vec::Vec};
+
+use chrono::{DateTime, Utc};
+
+use crate::amount::{Amount, RoundingMode, SCALE};
+
+use super::{validator::parse_amount, ParseTransactionError};
+
+/// Field separator of input, unless options say otherwise.
+pub const DEFAULT_DELIMITER: char = ',';
+
+/// Symbols lenient amounts may start with.
+const CURRENCY_SYMBOLS: [char; 4] = ['$', '€', '£', '¥'];
+
+/// Position of each field within an input row. Empty field, e.g. amount of dispute, counts as absent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnMapping {
+    pub transaction_type: usize,
+    pub client: usize,
+    pub transaction: usize,
+    pub amount: usize,
+    /// optional RFC 3339 timestamp column, None when input has no such column.
+    pub timestamp: Option<usize>,
+}
+
+impl Default for ColumnMapping {
+    /// `type,client,tx,amount`
+    fn default() -> Self {
+        ColumnMapping { transaction_type: 0, client: 1, transaction: 2, amount: 3, timestamp: None }
+    }
+}
+
+impl ColumnMapping {
+    /// Mapping read off header row naming columns `type`, `client`, `tx` and optionally `amount` and `timestamp`,
+    /// in any order.
+    /// None if line is not such header. Without amount column every row is taken to have no amount.
+    pub fn from_header(header: &str) -> Option<ColumnMapping> {
+        ColumnMapping::from_header_with(header, DEFAULT_DELIMITER)
+    }
+
+    /// Same as from_header, for header with fields separated by delimiter.
+    pub fn from_header_with(header: &str, delimiter: char) -> Option<ColumnMapping> {
+        let names = split(header, delimiter);
+        let position = |name: &str| names.iter().position(|each| each.eq_ignore_ascii_case(name));
+        Some(ColumnMapping {
+            transaction_type: position("type")?,
+            client: position("client")?,
+            transaction: position("tx")?,
+            amount: position("amount").unwrap_or(usize::MAX),
+            timestamp: position("timestamp"),
+        })
+    }
+}
+
+/// Controls how an input row is read into RawTransaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseOptions {
+    pub columns: ColumnMapping,
+    /// separates fields, e.g. `\t` or `;` for partners not sending csv. Spaces around fields are trimmed whatever it is.
+    pub delimiter: char,
+    /// read amounts like `$1,234.56`, dropping leading currency symbol and thousands separators.
+    /// As comma also separates fields, amount with separators has to be quoted, e.g. `deposit, 1, 1, "$1,234.56"`.
+    /// Row with more fields than columns is rejected, as that is what unquoted amount with separators looks like.
+    pub lenient_amounts: bool,
+    /// how amount with more than four decimal places is rounded. None rejects such amount.
+    /// When processor reads rows for an engine, rounding of engine, i.e. EngineConfig::rounding, takes over.
+    pub rounding: Option<RoundingMode>,
+    /// amount written with more decimal places than this is rejected, e.g. 2 for deployments taking cents only.
+    /// Places are counted as written, so with 2 `1.500` is rejected. Rounding, which is for places beyond four,
+    /// takes over only when this is four or more.
+    pub max_decimal_places: u32,
+}
+
+impl Default for ParseOptions {
+    /// Comma separated `type,client,tx,amount`, with plain amounts.
+    fn default() -> Self {
+        ParseOptions {
+            columns: ColumnMapping::default(),
+            delimiter: DEFAULT_DELIMITER,
+            lenient_amounts: false,
+            rounding: None,
+            max_decimal_places: SCALE,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Splits input into fields, ordered as `type, client, tx, amount` whatever order they came in.
+    /// Ordering stops at first field absent or empty in input.
+    pub(crate) fn fields<'a>(&self, input: &'a str) -> Vec<&'a str> {
+        let splitted = split(input, self.delimiter);
+        let columns = &self.columns;
+        [columns.transaction_type, columns.client, columns.transaction, columns.amount].iter()
+            .map_while(|index| splitted.get(*index).copied().filter(|field| !field.is_empty()))
+            .collect()
+    }
+
+    /// true if input has non empty fields beyond columns of mapping, e.g. `deposit,1,1,$1,234.56` where amount
+    /// with thousands separators is not quoted.
+    pub(crate) fn has_extra_fields(&self, input: &str) -> bool {
+        let columns = &self.columns;
+        let expected = [columns.transaction_type, columns.client, columns.transaction, columns.amount].iter()
+            .chain(columns.timestamp.iter())
+            .filter(|index| **index != usize::MAX)
+            .count();
+        split(input, self.delimiter).iter().skip(expected).any(|field| !field.is_empty())
+    }
+
+    /// Amount in field, None unless it is greater than zero. See lenient_amounts and rounding.
+    pub(crate) fn amount(&self, field: &str) -> Option<Amount> {
+        if !self.lenient_amounts {
+            return self.parse_amount(field);
+        }
+        let field = field.strip_prefix(CURRENCY_SYMBOLS).unwrap_or(field);
+        self.parse_amount(&without_thousands_separators(field)?)
+    }
+
+    fn parse_amount(&self, field: &str) -> Option<Amount> {
+        let decimal_places = field.split_once('.').map_or(0, |(_, fraction)| fraction.len());
+        let rounded = self.rounding.is_some() && self.max_decimal_places >= SCALE;
+        if decimal_places > self.max_decimal_places as usize && !rounded {
+            return None;
+        }
+        match self.rounding {
+            None => parse_amount(field),
+            Some(rounding) => Amount::parse_rounded(field, rounding).ok().filter(Amount::is_positive),
+        }
+    }
+
+    /// Timestamp of input, None if there is no timestamp column or it is left empty.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn timestamp(&self, input: &str) -> Result<Option<DateTime<Utc>>, ParseTransactionError> {
+        let field = self.columns.timestamp
+            .and_then(|index| split(input, self.delimiter).get(index).copied())
+            .filter(|field| !field.is_empty());
+        match field {
+            None => Ok(None),
+            Some(field) => DateTime::parse_from_rfc3339(field)
+                .map(|timestamp| Some(timestamp.with_timezone(&Utc)))
+                .map_err(|_| ParseTransactionError::InvalidTimestamp),
+        }
+    }
+}
+
+/// Fields of row separated by delimiter are trimmed and kept at their position, even when empty.
+/// Delimiter within double quotes does not separate fields, and quotes around field are dropped.
+/// Only comma separated input falls back to splitting on spaces, for row without comma.
+/// With any other delimiter space is never a separator, so that fields can not be told apart two ways.
+fn split(input: &str, delimiter: char) -> Vec<&str> {
+    if input.contains(delimiter) || delimiter != DEFAULT_DELIMITER {
+        let mut fields = Vec::new();
+        let mut start = 0;
+        let mut quoted = false;
+        for (index, c) in input.char_indices() {
+            match c {
+                '"' => quoted = !quoted,
+                c if c == delimiter && !quoted => {
+                    fields.push(unquote(&input[start..index]));
+                    start = index + c.len_utf8();
+                },
+                _ => {},
+            }
+        }
+        fields.push(unquote(&input[start..]));
+        fields
+    } else {
+        input.split(' ').filter(|each| !each.is_empty()).collect()
+    }
+}
+
+/// Amount with thousands separators dropped, None if they are not between groups of whole part,
+/// which are one to three digits followed by exactly three digits each, e.g. `1,234.5` but not `1,0.5` or `12,34`.
+fn without_thousands_separators(field: &str) -> Option<String> {
+    if !field.contains(',') {
+        return Some(field.to_string());
+    }
+    let whole = field.split_once('.').map_or(field, |(whole, fraction)| if fraction.contains(',') { "" } else { whole });
+    let mut groups = whole.split(',');
+    let digits = |group: &str| group.bytes().all(|digit| digit.is_ascii_digit());
+    let leading = groups.next().filter(|group| (1..=3).contains(&group.len()) && digits(group));
+    if leading.is_none() || !groups.all(|group| group.len() == 3 && digits(group)) {
+        return None;
+    }
+    Some(field.replace(',', ""))
+}
+
+fn unquote(field: &str) -> &str {
+    let field = field.trim();
+    field.strip_prefix('"').and_then(|field| field.strip_suffix('"')).unwrap_or(field)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::RawTransaction;
+
+    #[test]
+    fn test_header_with_shuffled_columns() {
+        let options = ParseOptions { columns: ColumnMapping::from_header("amount, tx, type, client").unwrap(), ..ParseOptions::default() };
+        assert_eq!(ColumnMapping { transaction_type: 2, client: 3, transaction: 1, amount: 0, timestamp: None }, options.columns);
+        assert_eq!("deposit,1,2,1.5", RawTransaction::parse_with("1.5, 2, deposit, 1", &options).unwrap().to_string());
+        assert_eq!("dispute,1,2,", RawTransaction::parse_with(", 2, dispute, 1", &options).unwrap().to_string());
+
+        let options = ParseOptions { columns: ColumnMapping::from_header("client,type,tx").unwrap(), ..ParseOptions::default() };
+        assert_eq!("resolve,3,4,", RawTransaction::parse_with("3,resolve,4", &options).unwrap().to_string());
+        assert!(RawTransaction::parse_with("3,deposit,4", &options).is_err());
+
+        assert_eq!(None, ColumnMapping::from_header("type, client, amount"));
+        assert_eq!(None, ColumnMapping::from_header("deposit, 1, 1, 1.0"));
+    }
+
+    #[test]
+    fn test_timestamp_column() {
+        let options = ParseOptions { columns: ColumnMapping::from_header("type, client, tx, amount, timestamp").unwrap(), ..ParseOptions::default() };
+        assert_eq!(Some(4), options.columns.timestamp);
+        let timestamp = options.timestamp("deposit, 1, 1, 1.0, 2024-01-02T03:04:05+01:00").unwrap().unwrap();
+        assert_eq!("2024-01-02 02:04:05 UTC", timestamp.to_string());
+        assert_eq!(Ok(None), options.timestamp("dispute, 1, 1, ,"));
+        assert_eq!(Ok(None), options.timestamp("deposit, 1, 1, 1.0"));
+        assert_eq!(Err(ParseTransactionError::InvalidTimestamp), options.timestamp("deposit, 1, 1, 1.0, yesterday"));
+        assert_eq!(Ok(None), ParseOptions::default().timestamp("deposit, 1, 1, 1.0, 2024-01-02T03:04:05Z"));
+    }
+
+    #[test]
+    fn test_lenient_amounts() {
+        let lenient = ParseOptions { lenient_amounts: true, ..ParseOptions::default() };
+        assert_eq!("deposit,1,1,1234.56", RawTransaction::parse_with("deposit, 1, 1, \"$1,234.56\"", &lenient).unwrap().to_string());
+        assert_eq!("deposit,1,1,1234.56", RawTransaction::parse_with("deposit, 1, 1, 1234.56", &lenient).unwrap().to_string());
+        assert_eq!("withdrawal,1,2,10", RawTransaction::parse_with("withdrawal, 1, 2, €10.00", &lenient).unwrap().to_string());
+        assert!(RawTransaction::parse_with("deposit, 1, 1, $-5", &lenient).is_err());
+        assert_eq!("deposit,1,1,1234567.5", RawTransaction::parse_with("deposit, 1, 1, \"1,234,567.5\"", &lenient).unwrap().to_string());
+
+        // separators only between groups of three digits of whole part.
+        for amount in ["1,0.5", "12,34", "1,2345", "1234,567", ",123", "1,,234", "1,234,", "1.234,5", "$,1"] {
+            let row = format!("deposit, 1, 1, \"{}\"", amount);
+            assert_eq!(Some(ParseTransactionError::InvalidAmount), RawTransaction::parse_with(&row, &lenient).err(), "{}", amount);
+        }
+
+        // unquoted amount with separators spills into extra fields.
+        assert_eq!(Some(ParseTransactionError::TooManyFields), RawTransaction::parse_with("deposit,2,2,$1,234.56", &lenient).err());
+        assert_eq!("dispute,1,1,", RawTransaction::parse_with("dispute,1,1,,", &lenient).unwrap().to_string());
+
+        // strict options keep rejecting them, while plain amount still parses.
+        let strict = ParseOptions::default();
+        assert_eq!(Some(ParseTransactionError::InvalidAmount), RawTransaction::parse_with("deposit, 1, 1, \"$1,234.56\"", &strict).err());
+        assert_eq!("deposit,1,1,1234.56", RawTransaction::parse_with("deposit, 1, 1, 1234.56", &strict).unwrap().to_string());
+    }
+
+    #[test]
+    fn test_quoted_fields() {
+        assert_eq!(vec!["deposit", "1", "1", "1,000"], split("deposit, \"1\", 1, \"1,000\"", ','));
+        assert_eq!(vec!["deposit", "1", "", ""], split("deposit,1,,", ','));
+    }
+
+    #[test]
+    fn test_rounding_of_extra_decimal_places() {
+        let options = |rounding| ParseOptions { rounding, ..ParseOptions::default() };
+        let row = "deposit, 1, 1, 0.50005";
+        assert_eq!(Some(ParseTransactionError::InvalidAmount), RawTransaction::parse_with(row, &options(None)).err());
+        assert_eq!("deposit,1,1,0.5001", RawTransaction::parse_with(row, &options(Some(RoundingMode::HalfUp))).unwrap().to_string());
+        assert_eq!("deposit,1,1,0.5", RawTransaction::parse_with(row, &options(Some(RoundingMode::HalfEven))).unwrap().to_string());
+        // amount rounded to zero moves no money.
+        assert!(RawTransaction::parse_with("deposit, 1, 1, 0.00001", &options(Some(RoundingMode::Truncate))).is_err());
+    }
+
+    #[test]
+    fn test_delimiters() {
+        let tab = ParseOptions { delimiter: '\t', ..ParseOptions::default() };
+        let semicolon = ParseOptions { delimiter: ';', ..ParseOptions::default() };
+        assert_eq!(vec!["deposit", "1", "1", "1.5"], split("deposit\t1 \t 1\t1.5", '\t'));
+        assert_eq!(vec!["deposit", "1", "1", "1;5"], split("deposit; 1; 1; \"1;5\"", ';'));
+        // space does not separate fields unless input is comma separated.
+        assert_eq!(vec!["deposit 1 1 1.5"], split("deposit 1 1 1.5", ';'));
+        assert_eq!(vec!["deposit", "1", "1", "1.5"], split("deposit 1 1 1.5", ','));
+
+        for (csv, other, options) in [("deposit, 1, 1, 1.5", "deposit\t1\t1\t1.5", &tab), ("dispute,1,1,", "dispute;1;1;", &semicolon)] {
+            assert_eq!(RawTransaction::parse(csv).unwrap().to_string(), RawTransaction::parse_with(other, options).unwrap().to_string());
+        }
+        assert_eq!(Some(ParseTransactionError::TooFewFields), RawTransaction::parse_with("deposit 1 1 1.5", &semicolon).err());
+        assert_eq!(Some(ColumnMapping::default()), ColumnMapping::from_header_with("type;client;tx;amount", ';'));
+    }
+
+    #[test]
+    fn test_max_decimal_places() {
+        let options = |max_decimal_places| ParseOptions { max_decimal_places, ..ParseOptions::default() };
+        let cents = options(2);
+        assert_eq!("deposit,1,1,1.5", RawTransaction::parse_with("deposit, 1, 1, 1.50", &cents).unwrap().to_string());
+        assert_eq!("deposit,1,1,2", RawTransaction::parse_with("deposit, 1, 1, 2", &cents).unwrap().to_string());
+        assert_eq!(Some(ParseTransactionError::InvalidAmount), RawTransaction::parse_with("deposit, 1, 1, 1.505", &cents).err());
+        assert_eq!(Some(ParseTransactionError::InvalidAmount), RawTransaction::parse_with("deposit, 1, 1, 1.500", &cents).err());
+        assert_eq!(Some(ParseTransactionError::InvalidAmount), RawTransaction::parse_with("dispute, 1, 1, 0.001", &cents).err());
+
+        let default = ParseOptions::default();
+        assert_eq!(options(4), default);
+        assert_eq!("deposit,1,1,1.5055", RawTransaction::parse_with("deposit, 1, 1, 1.5055", &default).unwrap().to_string());
+        assert_eq!(Some(ParseTransactionError::InvalidAmount), RawTransaction::parse_with("deposit, 1, 1, 1.50551", &default).err());
+
+        // rounding does not get around maximum below four places.
+        let rounded = |max_decimal_places| ParseOptions { rounding: Some(RoundingMode::HalfUp), ..options(max_decimal_places) };
+        assert!(RawTransaction::parse_with("deposit, 1, 1, 1.505", &rounded(2)).is_err());
+        assert_eq!("deposit,1,1,1.5056", RawTransaction::parse_with("deposit, 1, 1, 1.50555", &rounded(4)).unwrap().to_string());
+    }
+}
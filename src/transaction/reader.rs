@@ -0,0 +1,96 @@
+use std::{fmt::Display, io::Read};
+
+use csv::{ReaderBuilder, Trim};
+use serde::Deserialize;
+
+use crate::amount::Amount;
+
+use super::{ParseTransactionError, RawTransaction, TransactionType};
+
+/// One row of `type, client, tx, amount` csv.
+#[derive(Debug, Deserialize)]
+struct TransactionRecord {
+    #[serde(rename = "type")]
+    transaction_type: String,
+    client: u64,
+    tx: u32,
+    amount: Option<Amount>,
+}
+
+#[derive(Debug)]
+pub enum ReadTransactionError {
+    /// row is not well formed csv, or its fields are not of expected type.
+    Csv(csv::Error),
+    /// row is well formed, but is not a valid transaction.
+    Parse(ParseTransactionError),
+}
+
+impl Display for ReadTransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadTransactionError::Csv(err) => write!(f, "{}", err),
+            ReadTransactionError::Parse(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ReadTransactionError {}
+
+/// Reads csv having `type, client, tx, amount` header. Fields are trimmed, quoted fields and
+/// a leading BOM are handled by csv reader, and amount can be left out for rows not needing it.
+pub fn read_transactions<R: Read>(r: R) -> impl Iterator<Item = Result<RawTransaction, ReadTransactionError>> {
+    read_transactions_with(r, b',')
+}
+
+/// Same as read_transactions, for input with fields separated by delimiter, e.g. `b'\t'` or `b';'`.
+pub fn read_transactions_with<R: Read>(r: R, delimiter: u8) -> impl Iterator<Item = Result<RawTransaction, ReadTransactionError>> {
+    ReaderBuilder::new()
+        .delimiter(delimiter)
+        .trim(Trim::All)
+        .flexible(true)
+        .from_reader(r)
+        .into_deserialize::<TransactionRecord>()
+        .map(|record| {
+            let record = record.map_err(ReadTransactionError::Csv)?;
+            let transaction_type = record.transaction_type.parse::<TransactionType>()
+                .map_err(ReadTransactionError::Parse)?;
+            RawTransaction::from_parts(transaction_type, record.client, record.tx, record.amount)
+                .map_err(ReadTransactionError::Parse)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_transactions() {
+        let input = "\u{feff}type, client, tx, amount\n\
+            deposit, 1, 1, 1.5\n\
+            \"withdrawal\",\"1\",\"2\",\"0.5\"\n\
+            dispute, 1, 1,\n\
+            resolve, 1, 1\n\
+            deposit, 1, 3,\n\
+            refund, 1, 4, 1.0\n\
+            deposit, x, 5, 1.0\n";
+        let results: Vec<Result<RawTransaction, ReadTransactionError>> = read_transactions(input.as_bytes()).collect();
+        assert_eq!(7, results.len());
+        assert!(matches!(results[0], Ok(RawTransaction::Deposit { client_id: 1, transaction_id: 1, .. })));
+        assert!(matches!(results[1], Ok(RawTransaction::Withdrawal { client_id: 1, transaction_id: 2, .. })));
+        assert!(matches!(results[2], Ok(RawTransaction::Dispute { client_id: 1, transaction_id: 1, amount: None })));
+        assert!(matches!(results[3], Ok(RawTransaction::Reslove { client_id: 1, transaction_id: 1 })));
+        assert!(matches!(results[4], Err(ReadTransactionError::Parse(ParseTransactionError::MissingAmount))));
+        assert!(matches!(results[5], Err(ReadTransactionError::Parse(ParseTransactionError::UnknownTransactionType))));
+        assert!(matches!(results[6], Err(ReadTransactionError::Csv(_))));
+    }
+
+    #[test]
+    fn test_read_other_delimiters() {
+        let rows = |input: &str, delimiter| -> Vec<String> {
+            read_transactions_with(input.as_bytes(), delimiter).map(|transaction| transaction.unwrap().to_string()).collect()
+        };
+        let csv = rows("type,client,tx,amount\ndeposit, 1, 1, 1.5\ndispute, 1, 1,\n", b',');
+        assert_eq!(csv, rows("type\tclient\ttx\tamount\ndeposit\t1\t1\t1.5\ndispute\t1\t1\t\n", b'\t'));
+        assert_eq!(csv, rows("type;client;tx;amount\ndeposit; 1; 1; 1.5\ndispute; 1; 1;\n", b';'));
+    }
+}
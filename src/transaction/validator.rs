@@ -1,49 +1,240 @@
-use std::cmp::Ordering;
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::fmt::Display;
 
-use super::TransactionType;
+use crate::amount::Amount;
+
+use super::{options::ParseOptions, RawTransaction, TransactionType};
+
+/// Referential problem found by check_references. position is index of transaction in checked stream.
+#[derive(Debug, PartialEq)]
+pub enum RefError {
+    /// referred transaction is not present anywhere in stream.
+    Missing { position: usize, transaction_id: u32 },
+    /// referred transaction is present, but only after referring transaction.
+    OutOfOrder { position: usize, transaction_id: u32, referred_position: usize },
+}
+
+/// Reason input line can not be parsed into a RawTransaction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseTransactionError {
+    /// line does not have type, client and tx.
+    TooFewFields,
+    UnknownTransactionType,
+    InvalidClientId,
+    InvalidTransactionId,
+    /// deposit, withdrawal or pending without amount, e.g. `deposit,1,1,` or `deposit,1,1, `.
+    MissingAmount,
+    InvalidAmount,
+    /// timestamp column is not RFC 3339 date time.
+    InvalidTimestamp,
+    /// line has fields beyond its columns, with lenient amounts, see ParseOptions::lenient_amounts.
+    TooManyFields,
+}
+
+impl Display for ParseTransactionError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ParseTransactionError::TooFewFields => write!(f, "too few fields"),
+            ParseTransactionError::UnknownTransactionType => write!(f, "unknown transaction type"),
+            ParseTransactionError::InvalidClientId => write!(f, "invalid client id"),
+            ParseTransactionError::InvalidTransactionId => write!(f, "invalid transaction id"),
+            ParseTransactionError::MissingAmount => write!(f, "missing amount"),
+            ParseTransactionError::InvalidAmount => write!(f, "invalid amount"),
+            ParseTransactionError::InvalidTimestamp => write!(f, "invalid timestamp"),
+            ParseTransactionError::TooManyFields => write!(f, "too many fields, quote amount with thousands separators"),
+        }
+    }
+}
 
 pub fn is_valid_input(input: &str) -> bool {
-    let splitted: Vec<&str> = input.split(&[',', ' ']).filter(|each| !each.is_empty()).collect();
-    if splitted.is_empty() || splitted.len() < 3 {
-        return false;
+    validate_input(input).is_ok()
+}
+
+pub fn is_valid_input_with(input: &str, options: &ParseOptions) -> bool {
+    validate_input_with(input, options).is_ok()
+}
+
+/// Checks input can be converted to RawTransaction, otherwise tells why not.
+/// Fields are separated by comma, or by space when row has no comma, and are trimmed.
+/// Blank amount, with or without spaces, is same as absent amount.
+pub fn validate_input(input: &str) -> Result<(), ParseTransactionError> {
+    validate_input_with(input, &ParseOptions::default())
+}
+
+/// Same as validate_input, but reads fields as per options.
+pub fn validate_input_with(input: &str, options: &ParseOptions) -> Result<(), ParseTransactionError> {
+    let splitted = options.fields(input);
+    if splitted.len() < 3 {
+        return Err(ParseTransactionError::TooFewFields);
+    }
+    if options.lenient_amounts && options.has_extra_fields(input) {
+        return Err(ParseTransactionError::TooManyFields);
     }
-    let trans_type = *splitted.get(0).unwrap();
+    let trans_type = *splitted.first().unwrap();
     let client_id = *splitted.get(1).unwrap();
     let trans_id = *splitted.get(2).unwrap();
     let optional_amount = splitted.get(3);
 
-    if !is_valid_transaction_type(trans_type)
-        || !is_valid_client_id(client_id)
-        || !is_valid_transaction_id(trans_id) {
-            return false;
-        }
+    let trans_type = trans_type.parse::<TransactionType>()?;
+    if !is_valid_client_id(client_id) {
+        return Err(ParseTransactionError::InvalidClientId);
+    }
+    if !is_valid_transaction_id(trans_id) {
+        return Err(ParseTransactionError::InvalidTransactionId);
+    }
 
-    if (TransactionType::Deposite.as_str().cmp(trans_type) == Ordering::Equal
-        || TransactionType::Withdrawal.as_str().cmp(trans_type) == Ordering::Equal)
-        && !optional_amount.map_or(false, |amount| is_valid_amount(*amount)) {
-            return false;
+    if trans_type.requires_amount() {
+        match optional_amount {
+            None => return Err(ParseTransactionError::MissingAmount),
+            Some(amount) if options.amount(amount).is_none() => return Err(ParseTransactionError::InvalidAmount),
+            Some(_) => {},
         }
-        
-    true
-}
-
-fn is_valid_transaction_type(input_type: &str) -> bool {
-    for trans_type in TransactionType::iterator() {
-        if trans_type.as_str().cmp(input_type) == Ordering::Equal {
-            return true
+    }
+    // dispute may name part of transaction disputed.
+    if trans_type == TransactionType::Dispute {
+        if let Some(amount) = optional_amount {
+            if options.amount(amount).is_none() {
+                return Err(ParseTransactionError::InvalidAmount);
+            }
         }
     }
-    false
+
+    Ok(())
 }
 
 fn is_valid_client_id(id: &str) -> bool {
-    id.parse::<u16>().is_ok()
+    id.parse::<u64>().is_ok()
 }
 
 fn is_valid_transaction_id(id: &str) -> bool {
     id.parse::<u32>().is_ok()
 }
 
-fn is_valid_amount(amount: &str) -> bool {
-    amount.parse::<f64>().is_ok()
-}
\ No newline at end of file
+/// Parses amount with at most four decimal places. Only amount strictly greater than zero moves money,
+/// so zero and negative amounts are rejected, and -0.0 is same as 0.0 for fixed point Amount.
+/// NaN, inf and exponents are not plain decimals and never parse.
+pub fn parse_amount(amount: &str) -> Option<Amount> {
+    amount.parse::<Amount>().ok().filter(Amount::is_positive)
+}
+
+/// Verifies every referring transaction (dispute, resolve, chargeback, capture) refers to
+/// a transaction that appears earlier in stream.
+/// First pass records position of every referable transaction, second pass checks referring ones against it.
+pub fn check_references<I: Iterator<Item = RawTransaction>>(iter: I) -> Vec<RefError> {
+    let transactions: Vec<RawTransaction> = iter.collect();
+    let mut referable_positions = BTreeMap::new();
+    for (position, transaction) in transactions.iter().enumerate() {
+        if transaction.is_non_refering() {
+            referable_positions.entry(transaction.transaction_id()).or_insert(position);
+        }
+    }
+
+    transactions.iter().enumerate()
+        .filter(|(_, transaction)| !transaction.is_non_refering())
+        .filter_map(|(position, transaction)| {
+            let transaction_id = transaction.transaction_id();
+            match referable_positions.get(&transaction_id) {
+                None => Some(RefError::Missing { position, transaction_id }),
+                Some(&referred_position) if referred_position > position => Some(RefError::OutOfOrder {
+                    position,
+                    transaction_id,
+                    referred_position,
+                }),
+                Some(_) => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_references() {
+        let transactions = vec![
+            RawTransaction::new("deposit, 1, 1, 1.0"),
+            RawTransaction::new("dispute, 1, 1"),
+            RawTransaction::new("dispute, 1, 7"),
+            RawTransaction::new("resolve, 2, 2"),
+            RawTransaction::new("deposit, 2, 2, 1.0"),
+        ];
+
+        assert_eq!(vec![
+            RefError::Missing { position: 2, transaction_id: 7 },
+            RefError::OutOfOrder { position: 3, transaction_id: 2, referred_position: 4 },
+        ], check_references(transactions.into_iter()));
+    }
+
+    #[test]
+    fn test_parse_reports_reason() {
+        assert!(matches!(RawTransaction::parse("deposit, 1, 1, 1.5"), Ok(RawTransaction::Deposit { .. })));
+        assert_eq!(Some(ParseTransactionError::UnknownTransactionType), RawTransaction::parse("refund, 1, 1, 1.5").err());
+        assert_eq!(Some(ParseTransactionError::TooFewFields), RawTransaction::parse("deposit, 1").err());
+        assert_eq!(Some(ParseTransactionError::InvalidClientId), RawTransaction::parse("deposit, -1, 1, 1.5").err());
+        assert_eq!(Some(ParseTransactionError::InvalidTransactionId), RawTransaction::parse("deposit, 1, x, 1.5").err());
+        assert_eq!(Some(ParseTransactionError::InvalidAmount), RawTransaction::parse("deposit, 1, 1, x").err());
+    }
+
+    #[test]
+    fn test_empty_amount_is_missing_amount() {
+        assert_eq!(Err(ParseTransactionError::MissingAmount), validate_input("deposit,1,1,"));
+        assert_eq!(Err(ParseTransactionError::MissingAmount), validate_input("deposit,1,1, "));
+        assert_eq!(Err(ParseTransactionError::InvalidAmount), validate_input("deposit,1,1,abc"));
+        assert_eq!(Ok(()), validate_input("dispute,1,1,"));
+    }
+
+    #[test]
+    fn test_custom_column_mapping() {
+        use crate::transaction::options::ColumnMapping;
+
+        let options = ParseOptions {
+            columns: ColumnMapping { transaction_type: 1, client: 0, transaction: 2, amount: 3, timestamp: None },
+            ..ParseOptions::default()
+        };
+        assert!(!is_valid_input("1, deposit, 2, 3.5"));
+        assert!(is_valid_input_with("1, deposit, 2, 3.5", &options));
+        match RawTransaction::new_with("1, deposit, 2, 3.5", &options) {
+            RawTransaction::Deposit { client_id, transaction_id, amount } => {
+                assert_eq!((1, 2, Amount::from_units(35_000)), (client_id, transaction_id, amount));
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(Err(ParseTransactionError::MissingAmount), validate_input_with("1, deposit, 2,", &options));
+    }
+
+    #[test]
+    fn test_zero_amount_rejected_regardless_of_sign() {
+        assert_eq!(None, parse_amount("-0.0"));
+        assert_eq!(None, parse_amount("0.0"));
+        assert_eq!(Some(Amount::from_units(15_000)), parse_amount("1.5"));
+        assert!(!is_valid_input("deposit,1,1,-0.0"));
+        assert!(!is_valid_input("deposit,1,1,0"));
+        assert!(is_valid_input("deposit,1,1,0.5"));
+    }
+
+    #[test]
+    fn test_more_than_four_decimal_places_rejected() {
+        assert!(is_valid_input("deposit,1,1,1.2345"));
+        assert_eq!(Err(ParseTransactionError::InvalidAmount), validate_input("deposit,1,1,1.23456"));
+    }
+
+    #[test]
+    fn test_non_positive_and_non_finite_amounts_rejected() {
+        for amount in ["-1.0", "0", "NaN", "inf", "-inf"] {
+            assert_eq!(None, parse_amount(amount), "{}", amount);
+            assert_eq!(Err(ParseTransactionError::InvalidAmount), validate_input(&format!("deposit,1,1,{}", amount)));
+            assert_eq!(Err(ParseTransactionError::InvalidAmount), validate_input(&format!("withdrawal,1,1,{}", amount)));
+        }
+    }
+
+    #[test]
+    fn test_transaction_type_from_str() {
+        for transaction_type in TransactionType::iterator() {
+            assert_eq!(Ok(*transaction_type), transaction_type.as_str().parse::<TransactionType>());
+        }
+        for invalid in ["", "Deposit", "refund", " deposit", "deposite"] {
+            assert_eq!(Err(ParseTransactionError::UnknownTransactionType), invalid.parse::<TransactionType>(), "{}", invalid);
+        }
+    }
+}
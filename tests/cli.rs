@@ -0,0 +1,283 @@
+use std::{env, fs, io::Write, process::{Command, Output, Stdio}};
+
+use flate2::{write::GzEncoder, Compression};
+
+fn run_cli(name: &str, input: &str, args: &[&str]) -> (bool, String) {
+    run_cli_with_file(&format!("{}.csv", name), input.as_bytes(), args)
+}
+
+/// Runs cli on file of given name holding content, returning whether it succeeded and its stdout.
+fn run_cli_with_file(file_name: &str, content: &[u8], args: &[&str]) -> (bool, String) {
+    run_cli_with_files(&[(file_name, content)], args)
+}
+
+/// Runs cli on files of given names holding their content, in given order.
+fn run_cli_with_files(files: &[(&str, &[u8])], args: &[&str]) -> (bool, String) {
+    let output = run_cli_output(files, args);
+    (output.status.success(), String::from_utf8(output.stdout).unwrap())
+}
+
+/// Same as run_cli_with_files, returning whole output of cli.
+fn run_cli_output(files: &[(&str, &[u8])], args: &[&str]) -> Output {
+    let paths: Vec<_> = files.iter()
+        .map(|(file_name, content)| {
+            let path = env::temp_dir().join(format!("payment-engine-{}-{}", std::process::id(), file_name));
+            fs::write(&path, content).unwrap();
+            path
+        })
+        .collect();
+    let output = Command::new(env!("CARGO_BIN_EXE_payment-engine"))
+        .args(&paths)
+        .args(args)
+        .env("RUST_LOG", "debug")
+        .output()
+        .unwrap();
+    for path in paths {
+        fs::remove_file(path).unwrap();
+    }
+    output
+}
+
+#[test]
+fn test_locked_account_diagnostics_stay_out_of_stdout() {
+    let input = "type, client, tx, amount\n\
+        deposit, 1, 1, 1.0\n\
+        dispute, 1, 1\n\
+        chargeback, 1, 1\n\
+        deposit, 1, 2, 5.0\n\
+        withdrawal, 1, 3, 1.0\n";
+    let (success, stdout) = run_cli("locked", input, &[]);
+    assert!(success);
+    let mut lines = stdout.lines();
+    assert_eq!(Some("client,available,held,total,locked"), lines.next());
+    assert_eq!(vec!["1, 0, 0, 0, true"], lines.collect::<Vec<_>>());
+}
+
+#[test]
+fn test_check_reports_errors_and_fails() {
+    let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\nwithdrawal, 1, 2, 5.0\n";
+    let (success, stdout) = run_cli("check-errors", input, &["--check"]);
+    assert!(!success);
+    assert_eq!("line 3: insufficient funds\n", stdout);
+
+    let (success, stdout) = run_cli("check-clean", "deposit, 1, 1, 1.0\n", &["--check"]);
+    assert!(success);
+    assert!(stdout.is_empty());
+}
+
+#[test]
+fn test_gzipped_input_gives_same_result() {
+    let input = "type, client, tx, amount\n\
+        deposit, 1, 1, 2.0\n\
+        deposit, 2, 2, 1.5\n\
+        withdrawal, 1, 3, 0.5\n\
+        dispute, 2, 2\n";
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(input.as_bytes()).unwrap();
+    let gzipped = encoder.finish().unwrap();
+
+    // clients come out in no particular order.
+    let sorted_lines = |(success, stdout): (bool, String)| {
+        assert!(success);
+        let mut lines: Vec<String> = stdout.lines().map(str::to_string).collect();
+        lines.sort();
+        lines
+    };
+    let plain = sorted_lines(run_cli("plain", input, &[]));
+    assert_eq!(3, plain.len());
+    assert_eq!(plain, sorted_lines(run_cli_with_file("gzipped.csv.gz", &gzipped, &[])));
+    // gzip is also recognized by its content, whatever the file is named.
+    assert_eq!(plain, sorted_lines(run_cli_with_file("gzipped-no-extension.csv", &gzipped, &[])));
+}
+
+#[test]
+fn test_files_processed_in_given_order() {
+    let first = "type, client, tx, amount\ndeposit, 1, 1, 2.0\ndeposit, 1, 2, 1.0\n";
+    let second = "type, client, tx, amount\ndispute, 1, 1\nwithdrawal, 1, 3, 5.0\n";
+    let files = [("day-1.csv", first.as_bytes()), ("day-2.csv", second.as_bytes())];
+
+    // dispute in second file holds deposit of first one.
+    assert_eq!((true, "client,available,held,total,locked\n1, 1, 2, 3, false\n".to_string()), run_cli_with_files(&files, &[]));
+
+    let (success, stdout) = run_cli_with_files(&files, &["--check"]);
+    assert!(!success);
+    assert!(stdout.ends_with("day-2.csv: line 3: insufficient funds\n"));
+    assert_eq!(1, stdout.lines().count());
+}
+
+#[test]
+fn test_status_column() {
+    let input = "type, client, tx, amount\n\
+        deposit, 1, 1, 1.0\n\
+        dispute, 1, 1\n\
+        chargeback, 1, 1\n";
+    let (success, stdout) = run_cli("status-column", input, &["--status-column"]);
+    assert!(success);
+    assert_eq!("client,available,held,total,locked,status\n1, 0, 0, 0, true, frozen\n", stdout);
+
+    let (_, stdout) = run_cli("no-status-column", input, &[]);
+    assert_eq!("client,available,held,total,locked\n1, 0, 0, 0, true\n", stdout);
+}
+
+/// Runs cli with input piped through stdin, returning whether it succeeded and its stdout.
+fn run_cli_with_stdin(input: &str, args: &[&str]) -> (bool, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_payment-engine"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    (output.status.success(), String::from_utf8(output.stdout).unwrap())
+}
+
+#[test]
+fn test_input_piped_through_stdin() {
+    let input = "type, client, tx, amount\ndeposit, 1, 1, 2.0\nwithdrawal, 1, 2, 0.5\n";
+    let (_, from_file) = run_cli("stdin", input, &[]);
+    for args in [&[][..], &["-"][..]] {
+        let (success, stdout) = run_cli_with_stdin(input, args);
+        assert!(success);
+        assert_eq!(from_file, stdout);
+    }
+    assert_eq!("client,available,held,total,locked\n1, 1.5, 0, 1.5, false\n", from_file);
+}
+
+#[test]
+fn test_strict_stops_at_first_invalid_line() {
+    let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\ndeposit, 1, x, 1.0\nnot a transaction\n";
+    let output = run_cli_output(&[("strict.csv", input.as_bytes())], &["--strict"]);
+    assert_eq!(Some(1), output.status.code());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(1, stderr.matches("line ").count());
+    assert!(stderr.ends_with("strict.csv: line 3: invalid transaction id: deposit, 1, x, 1.0\n"));
+
+    // without the flag invalid lines are skipped.
+    let (success, stdout) = run_cli("not-strict", input, &[]);
+    assert!(success);
+    assert_eq!("client,available,held,total,locked\n1, 1, 0, 1, false\n", stdout);
+}
+
+#[test]
+fn test_diagnostics_carry_line_number() {
+    let input = "type, client, tx, amount\n\
+        deposit, 1, 1, 1.0\n\
+        \n\
+        deposit, 1, abc, 1.0\n\
+        withdrawal, 1, 2, 5.0\n\
+        deposit, 1, 3, 1.0\n";
+    let output = run_cli_output(&[("line-numbers.csv", input.as_bytes())], &[]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("line 4: Skipping invalid input deposit, 1, abc, 1.0 as invalid transaction id"), "{}", stderr);
+    assert!(stderr.contains("line 5: Skipping withdrawal, 1, 2, 5.0 as insufficient funds"), "{}", stderr);
+    assert!(!stderr.contains("line 6:"), "{}", stderr);
+}
+
+#[test]
+fn test_totals_only() {
+    let input = "type, client, tx, amount\ndeposit, 2, 1, 2.0\ndeposit, 1, 2, 1.5\ndispute, 2, 1\n";
+    let (success, stdout) = run_cli("totals-only", input, &["--totals-only"]);
+    assert!(success);
+    assert_eq!("client,total\n1, 1.5\n2, 2\n", stdout);
+}
+
+#[test]
+fn test_overflowing_deposit_is_skipped() {
+    let input = "type,client,tx,amount\ndeposit,1,1,900000000000000\ndeposit,1,2,900000000000000\n";
+    let (success, stdout) = run_cli("overflow", input, &[]);
+    assert!(success);
+    assert_eq!("client,available,held,total,locked\n1, 900000000000000, 0, 900000000000000, false\n", stdout);
+}
+
+#[test]
+fn test_unquoted_lenient_amount_is_rejected() {
+    let input = "type,client,tx,amount\ndeposit,1,1,\"$1,234.56\"\ndeposit,2,2,$1,234.56\n";
+    let output = run_cli_output(&[("unquoted-amount.csv", input.as_bytes())], &["--lenient-amounts"]);
+    assert!(output.status.success());
+    assert_eq!("client,available,held,total,locked\n1, 1234.56, 0, 1234.56, false\n", String::from_utf8(output.stdout).unwrap());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("line 3: Skipping invalid input deposit,2,2,$1,234.56 as too many fields, quote amount with thousands separators"), "{}", stderr);
+}
+
+#[test]
+fn test_missing_input_file_exits_with_error() {
+    let path = env::temp_dir().join(format!("payment-engine-{}-missing.csv", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_payment-engine")).arg(&path).output().unwrap();
+    assert_eq!(Some(2), output.status.code());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with(&format!("{}: ", path.display())), "{}", stderr);
+    assert!(!stderr.contains("panicked"));
+}
+
+#[test]
+fn test_output_flags_are_mutually_exclusive() {
+    let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\n";
+    for args in [&["--totals-only", "--status-column"][..], &["--format", "json", "--group-by-locked"],
+        &["--format", "csv", "--totals-only"], &["--group-by-locked", "--status-column"]] {
+        let output = run_cli_output(&[("exclusive.csv", input.as_bytes())], args);
+        assert_eq!(Some(2), output.status.code(), "{:?}", args);
+        assert!(output.stdout.is_empty());
+        assert!(String::from_utf8(output.stderr).unwrap().contains("cannot be used with"), "{:?}", args);
+    }
+    let (success, stdout) = run_cli("exclusive-csv", input, &["--format", "csv"]);
+    assert!(success);
+    assert_eq!("client,available,held,total,locked\n1, 1, 0, 1, false\n", stdout);
+}
+
+#[test]
+fn test_truncated_gzip_is_reported_and_ends_input() {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all("type, client, tx, amount\ndeposit, 1, 1, 1.0\n".repeat(100).as_bytes()).unwrap();
+    let gzipped = encoder.finish().unwrap();
+    let path = env::temp_dir().join(format!("payment-engine-{}-truncated.csv.gz", std::process::id()));
+    fs::write(&path, &gzipped[..20]).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_payment-engine"))
+        .arg(&path)
+        .env("RUST_LOG", "debug")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let started = std::time::Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if started.elapsed() > std::time::Duration::from_secs(10) {
+            child.kill().unwrap();
+            panic!("cli kept reading truncated gzip");
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    let output = child.wait_with_output().unwrap();
+    fs::remove_file(path).unwrap();
+    assert!(output.status.success());
+    assert_eq!("client,available,held,total,locked\n", String::from_utf8(output.stdout).unwrap());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(1, stderr.matches("line 1").count(), "{}", stderr);
+}
+
+#[test]
+fn test_rounding_of_extra_decimal_places() {
+    let input = "type, client, tx, amount\ndeposit, 1, 1, 0.50005\n";
+    for (args, expected) in [(&[][..], "0.5"), (&["--rounding", "half-up"], "0.5001"), (&["--rounding", "truncate"], "0.5")] {
+        let (success, stdout) = run_cli("rounding", input, args);
+        assert!(success);
+        assert_eq!(format!("client,available,held,total,locked\n1, {0}, 0, {0}, false\n", expected), stdout, "{:?}", args);
+    }
+    let (success, stdout) = run_cli("rounding-check", input, &["--check"]);
+    assert!(success, "{}", stdout);
+}
+
+#[test]
+fn test_unwritable_report_path_exits_with_error() {
+    let report_path = env::temp_dir().join(format!("payment-engine-{}-missing-dir", std::process::id())).join("report.json");
+    let report_path = report_path.to_str().unwrap();
+    let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\n";
+    let output = run_cli_output(&[("unwritable-report.csv", input.as_bytes())], &["--report", report_path]);
+    assert_eq!(Some(2), output.status.code());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&format!("{}: ", report_path)), "{}", stderr);
+    assert!(!stderr.contains("panicked"));
+}